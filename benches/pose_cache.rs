@@ -0,0 +1,259 @@
+//! Round trips and time per frame for locating spaces, asking the server every time versus once per frame through the pose cache.
+//!
+//! Both go through the runtime's own [`Ipc`], to a stub server listening where the real one would, which answers each method the way the real one does.
+
+use openxr_stardust::{
+	events::StardustScenegraph,
+	ipc::Ipc,
+	pose_cache::{PoseCache, StardustPoseSnapshot},
+	space::StardustSpaceLocation,
+	util::{delay_from_time, now, StardustFov, StardustPose},
+};
+use openxr_sys::{Time, ViewConfigurationType};
+use serde::Serialize;
+use stardust_xr::{
+	messenger,
+	scenegraph::{Scenegraph, ScenegraphError},
+	schemas::flex::{deserialize, serialize},
+};
+use std::{
+	collections::HashMap,
+	fs::{self, DirBuilder},
+	os::unix::{fs::DirBuilderExt, net::UnixListener},
+	sync::{
+		atomic::{AtomicU32, Ordering},
+		mpsc, Arc,
+	},
+	thread,
+	time::{Duration, Instant},
+};
+
+const FRAMES: u32 = 200;
+const SPACE_COUNT: usize = 8;
+const LOCATES_PER_FRAME: [usize; 4] = [1, 4, 16, 64];
+const FRAME_PERIOD: i64 = 1_000_000_000 / 90;
+const SESSION_NODE_PATH: &str = "/openxr/system1/session";
+
+#[derive(Serialize)]
+struct Location {
+	pose: StardustPose,
+	position_valid: bool,
+	orientation_valid: bool,
+	position_tracked: bool,
+	orientation_tracked: bool,
+}
+#[derive(Serialize)]
+struct ViewLocation {
+	pose: StardustPose,
+	fov: StardustFov,
+}
+#[derive(Serialize)]
+struct ViewLocations {
+	views: Vec<ViewLocation>,
+	position_valid: bool,
+	orientation_valid: bool,
+	position_tracked: bool,
+	orientation_tracked: bool,
+}
+#[derive(Serialize)]
+struct Snapshot {
+	spaces: HashMap<String, Vec<Option<Location>>>,
+	views: Vec<ViewLocations>,
+}
+
+fn location(offset: f32) -> Location {
+	Location {
+		pose: StardustPose {
+			position: [offset, 1.6, -offset].into(),
+			orientation: [0.0, 0.0, 0.0, 1.0].into(),
+		},
+		position_valid: true,
+		orientation_valid: true,
+		position_tracked: true,
+		orientation_tracked: true,
+	}
+}
+fn space_node_path(index: usize) -> String {
+	format!("{SESSION_NODE_PATH}/space{index}")
+}
+
+/// Answers `locate` and `pose_snapshot`, counting every method so the round trips can't be miscounted on our end.
+#[derive(Default)]
+struct StubServer {
+	methods: AtomicU32,
+}
+impl Scenegraph for StubServer {
+	fn execute_method(
+		&self,
+		_path: &str,
+		method: &str,
+		data: &[u8],
+	) -> Result<Vec<u8>, ScenegraphError> {
+		self.methods.fetch_add(1, Ordering::Relaxed);
+		let serialized = match method {
+			"locate" => serialize(Some(location(0.5))),
+			"pose_snapshot" => {
+				let sample_count = deserialize::<Vec<f64>>(data).unwrap().len();
+				let spaces = (0..SPACE_COUNT)
+					.map(|i| {
+						let samples = (0..sample_count)
+							.map(|s| Some(location(i as f32 + s as f32 * 0.01)))
+							.collect();
+						(space_node_path(i), samples)
+					})
+					.collect();
+				let views = (0..sample_count)
+					.map(|_| ViewLocations {
+						views: (0..2)
+							.map(|_| ViewLocation {
+								pose: location(0.0).pose,
+								fov: StardustFov {
+									angle_left: -0.8,
+									angle_right: 0.8,
+									angle_up: 0.8,
+									angle_down: -0.8,
+								},
+							})
+							.collect(),
+						position_valid: true,
+						orientation_valid: true,
+						position_tracked: true,
+						orientation_tracked: true,
+					})
+					.collect();
+				serialize(Snapshot { spaces, views })
+			}
+			_ => return Err(ScenegraphError::MethodNotFound),
+		};
+		Ok(serialized.unwrap())
+	}
+}
+
+/// Serve one client until it hangs up.
+fn serve(listener: UnixListener, server: Arc<StubServer>) {
+	let runtime = tokio::runtime::Builder::new_current_thread()
+		.enable_io()
+		.build()
+		.unwrap();
+	runtime.block_on(async move {
+		listener.set_nonblocking(true).unwrap();
+		let listener = tokio::net::UnixListener::from_std(listener).unwrap();
+		let (connection, _) = listener.accept().await.unwrap();
+		let (mut message_sender, mut message_receiver) = messenger::create(connection);
+		tokio::spawn(async move {
+			let _ = message_sender.flush().await;
+		});
+		while message_receiver.dispatch(&*server).await.is_ok() {}
+	});
+}
+
+/// Every locate asks the server, like before the cache.
+fn naive_frame(ipc: &Ipc, locates: usize, display_time: Time) {
+	for i in 0..locates {
+		let data = serialize((
+			space_node_path((i + 1) % SPACE_COUNT),
+			delay_from_time(display_time),
+		))
+		.unwrap();
+		let location = ipc
+			.method(
+				&space_node_path(i % SPACE_COUNT),
+				"locate",
+				data,
+				Some(ipc.method_timeout()),
+			)
+			.unwrap()
+			.unwrap();
+		let location: Option<StardustSpaceLocation> = deserialize(&location).unwrap();
+		std::hint::black_box(location);
+	}
+}
+
+/// One snapshot per frame, then every locate is answered locally.
+fn cached_frame(ipc: &Ipc, locates: usize, display_time: Time) {
+	let times = vec![
+		now(),
+		display_time,
+		Time::from_nanos(display_time.as_nanos() + FRAME_PERIOD),
+	];
+	let data = serialize(PoseCache::sample_delays(&times)).unwrap();
+	let snapshot = ipc
+		.method(
+			SESSION_NODE_PATH,
+			"pose_snapshot",
+			data,
+			Some(ipc.method_timeout()),
+		)
+		.unwrap()
+		.unwrap();
+	let snapshot: StardustPoseSnapshot = deserialize(&snapshot).unwrap();
+	let cache = PoseCache::new(times, ViewConfigurationType::PRIMARY_STEREO, snapshot).unwrap();
+	for i in 0..locates {
+		// somewhere between now and display, like an app locating controllers for its simulation
+		let time = Time::from_nanos(display_time.as_nanos() - (i as i64 % 4) * 1_000_000);
+		let location = cache
+			.locate_space(
+				&space_node_path(i % SPACE_COUNT),
+				&space_node_path((i + 1) % SPACE_COUNT),
+				time,
+			)
+			.unwrap();
+		std::hint::black_box(location);
+	}
+}
+
+fn measure(
+	ipc: &Ipc,
+	server: &StubServer,
+	locates: usize,
+	frame: fn(&Ipc, usize, Time),
+) -> (f64, Duration) {
+	let methods_before = server.methods.load(Ordering::Relaxed);
+	let start = Instant::now();
+	for _ in 0..FRAMES {
+		let display_time = Time::from_nanos(now().as_nanos() + FRAME_PERIOD * 2);
+		frame(ipc, locates, display_time);
+	}
+	let elapsed = start.elapsed();
+	let round_trips = server.methods.load(Ordering::Relaxed) - methods_before;
+	(round_trips as f64 / FRAMES as f64, elapsed / FRAMES)
+}
+
+fn main() {
+	// the client finds the server's socket through these, so point it at one of our own
+	let runtime_dir =
+		std::env::temp_dir().join(format!("openxr-stardust-bench-{}", std::process::id()));
+	DirBuilder::new().mode(0o700).create(&runtime_dir).unwrap();
+	std::env::set_var("XDG_RUNTIME_DIR", &runtime_dir);
+	std::env::set_var("STARDUST_INSTANCE", "0");
+	let listener = UnixListener::bind(runtime_dir.join("stardust-0")).unwrap();
+
+	let server = Arc::new(StubServer::default());
+	let serve_thread = thread::spawn({
+		let server = server.clone();
+		move || serve(listener, server)
+	});
+	let (server_events, _server_events) = mpsc::channel();
+	let ipc = Ipc::connect(StardustScenegraph::new(server_events)).unwrap();
+
+	println!(
+		"{:>16} {:>22} {:>22} {:>16} {:>16}",
+		"locates/frame",
+		"round trips (naive)",
+		"round trips (cached)",
+		"naive/frame",
+		"cached/frame"
+	);
+	for locates in LOCATES_PER_FRAME {
+		let (naive_round_trips, naive_time) = measure(&ipc, &server, locates, naive_frame);
+		let (cached_round_trips, cached_time) = measure(&ipc, &server, locates, cached_frame);
+		println!(
+			"{:>16} {:>22} {:>22} {:>16?} {:>16?}",
+			locates, naive_round_trips, cached_round_trips, naive_time, cached_time
+		);
+	}
+
+	drop(ipc);
+	serve_thread.join().unwrap();
+	fs::remove_dir_all(runtime_dir).unwrap();
+}
@@ -0,0 +1,187 @@
+use crate::{
+	handle::Handle,
+	util::{time_from_delay, StardustPose},
+	XrResult,
+};
+use openxr_sys::{
+	EventDataBuffer, EventDataEventsLost, Instance, Posef, ReferenceSpaceType, SessionState,
+	StructureType, Time,
+};
+use serde::Deserialize;
+use stardust_xr::{
+	scenegraph::{Scenegraph, ScenegraphError},
+	schemas::flex::{deserialize, flexbuffers::DeserializationError},
+};
+use std::{
+	collections::VecDeque,
+	mem::{size_of, zeroed},
+	ptr,
+	sync::mpsc::Sender,
+};
+
+/// Events the app hasn't polled yet, past this the oldest are kept and the rest are counted as lost.
+const MAX_QUEUED_EVENTS: usize = 64;
+
+/// Something the Stardust server told us happened, waiting to be applied to the instance.
+#[derive(Debug)]
+pub enum ServerEvent {
+	InstanceLossPending {
+		loss_time: Time,
+	},
+	SessionStateChanged {
+		session_path: String,
+		state: SessionState,
+	},
+	ReferenceSpaceChangePending {
+		session_path: String,
+		reference_space_type: ReferenceSpaceType,
+		change_time: Time,
+		pose_in_previous_space: Option<Posef>,
+	},
+	InteractionProfileChanged {
+		session_path: String,
+	},
+}
+
+#[derive(Debug, Deserialize)]
+struct ReferenceSpaceChangeInfo {
+	reference_space_type: i32,
+	/// Seconds from now until the change takes effect
+	change_delay: f64,
+	pose_in_previous_space: Option<StardustPose>,
+}
+
+/// The `/openxr` side of the client scenegraph, turning server signals into [`ServerEvent`]s.
+pub struct StardustScenegraph {
+	server_events: Sender<ServerEvent>,
+}
+impl StardustScenegraph {
+	pub fn new(server_events: Sender<ServerEvent>) -> Self {
+		StardustScenegraph { server_events }
+	}
+
+	fn parse_signal(
+		&self,
+		path: &str,
+		method: &str,
+		data: &[u8],
+	) -> Result<ServerEvent, ScenegraphError> {
+		let signal_error = |error: DeserializationError| ScenegraphError::SignalError {
+			error: error.into(),
+		};
+		if path == "/openxr" {
+			return match method {
+				"instance_loss_pending" => Ok(ServerEvent::InstanceLossPending {
+					loss_time: time_from_delay(deserialize(data).map_err(signal_error)?),
+				}),
+				_ => Err(ScenegraphError::SignalNotFound),
+			};
+		}
+		if !path.starts_with("/openxr/system") {
+			return Err(ScenegraphError::NodeNotFound);
+		}
+
+		let session_path = path.to_string();
+		match method {
+			"state_changed" => Ok(ServerEvent::SessionStateChanged {
+				session_path,
+				state: SessionState::from_raw(deserialize(data).map_err(signal_error)?),
+			}),
+			"reference_space_change_pending" => {
+				let info: ReferenceSpaceChangeInfo = deserialize(data).map_err(signal_error)?;
+				Ok(ServerEvent::ReferenceSpaceChangePending {
+					session_path,
+					reference_space_type: ReferenceSpaceType::from_raw(info.reference_space_type),
+					change_time: time_from_delay(info.change_delay),
+					pose_in_previous_space: info.pose_in_previous_space.map(Posef::from),
+				})
+			}
+			"interaction_profile_changed" => {
+				Ok(ServerEvent::InteractionProfileChanged { session_path })
+			}
+			_ => Err(ScenegraphError::SignalNotFound),
+		}
+	}
+}
+impl Scenegraph for StardustScenegraph {
+	fn send_signal(&self, path: &str, method: &str, data: &[u8]) -> Result<(), ScenegraphError> {
+		let event = self.parse_signal(path, method, data)?;
+		self.server_events
+			.send(event)
+			.map_err(|e| ScenegraphError::SignalError { error: e.into() })
+	}
+	fn execute_method(
+		&self,
+		_path: &str,
+		_method: &str,
+		_data: &[u8],
+	) -> Result<Vec<u8>, ScenegraphError> {
+		Err(ScenegraphError::MethodNotFound)
+	}
+}
+
+/// FIFO of `XrEventDataBuffer` payloads waiting for `xrPollEvent`.
+#[derive(Default)]
+pub struct EventQueue {
+	events: VecDeque<EventDataBuffer>,
+}
+// the only pointers in queued events are their null next chains
+unsafe impl Send for EventQueue {}
+impl EventQueue {
+	/// Queue any `XrEventData*` struct, which must fit inside an `XrEventDataBuffer`.
+	pub fn push<E: Copy>(&mut self, event: E) {
+		assert!(size_of::<E>() <= size_of::<EventDataBuffer>());
+		// the last slot is kept free for the events lost marker
+		if self.events.len() >= MAX_QUEUED_EVENTS - 1 {
+			self.event_lost();
+			return;
+		}
+		let mut buffer: EventDataBuffer = unsafe { zeroed() };
+		unsafe { ptr::write(&mut buffer as *mut EventDataBuffer as *mut E, event) };
+		self.events.push_back(buffer);
+	}
+	fn event_lost(&mut self) {
+		if let Some(last) = self
+			.events
+			.back_mut()
+			.filter(|e| e.ty == StructureType::EVENT_DATA_EVENTS_LOST)
+		{
+			let lost = unsafe { &mut *(last as *mut EventDataBuffer as *mut EventDataEventsLost) };
+			lost.lost_event_count += 1;
+			return;
+		}
+		let mut buffer: EventDataBuffer = unsafe { zeroed() };
+		unsafe {
+			ptr::write(
+				&mut buffer as *mut EventDataBuffer as *mut EventDataEventsLost,
+				EventDataEventsLost {
+					ty: EventDataEventsLost::TYPE,
+					next: ptr::null(),
+					lost_event_count: 1,
+				},
+			)
+		};
+		self.events.push_back(buffer);
+	}
+	pub fn pop(&mut self) -> Option<EventDataBuffer> {
+		self.events.pop_front()
+	}
+}
+
+/// # Safety
+/// https://www.khronos.org/registry/OpenXR/specs/1.0/html/xrspec.html#xrPollEvent
+#[no_mangle]
+pub unsafe extern "system" fn xrPollEvent(
+	instance: Instance,
+	event_data: &mut EventDataBuffer,
+) -> XrResult {
+	wrap_oxr! {
+		// the app finds out the instance is lost through its events, so those still have to come through
+		let stardust_instance = instance.get_stardust_even_if_lost()?;
+		match stardust_instance.poll_event() {
+			Some(event) => *event_data = event,
+			None if stardust_instance.lost() => Err(XrResult::ERROR_INSTANCE_LOST)?,
+			None => Err(XrResult::EVENT_UNAVAILABLE)?,
+		}
+	}
+}
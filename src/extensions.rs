@@ -1,10 +1,51 @@
-use openxr_sys::MND_HEADLESS_EXTENSION_NAME;
+use openxr_sys::{
+	KHR_composition_layer_cube_SPEC_VERSION, KHR_composition_layer_cylinder_SPEC_VERSION,
+	KHR_composition_layer_depth_SPEC_VERSION, KHR_composition_layer_equirect2_SPEC_VERSION,
+	KHR_opengl_enable_SPEC_VERSION, KHR_vulkan_enable2_SPEC_VERSION, MNDX_egl_enable_SPEC_VERSION,
+	MND_headless_SPEC_VERSION, KHR_COMPOSITION_LAYER_CUBE_EXTENSION_NAME,
+	KHR_COMPOSITION_LAYER_CYLINDER_EXTENSION_NAME, KHR_COMPOSITION_LAYER_DEPTH_EXTENSION_NAME,
+	KHR_COMPOSITION_LAYER_EQUIRECT2_EXTENSION_NAME, KHR_OPENGL_ENABLE_EXTENSION_NAME,
+	KHR_VULKAN_ENABLE2_EXTENSION_NAME, MNDX_EGL_ENABLE_EXTENSION_NAME, MND_HEADLESS_EXTENSION_NAME,
+};
 
 use crate::{
 	oxr::ExtensionProperties,
 	util::{copy_str_to_buffer, enumerate},
 };
-use std::{ffi::c_char, ptr};
+use std::{
+	ffi::{c_char, CStr},
+	ptr,
+};
+
+/// Every extension the runtime supports, along with its spec version.
+const EXTENSIONS: [(&[u8], u32); 8] = [
+	(MND_HEADLESS_EXTENSION_NAME, MND_headless_SPEC_VERSION),
+	(
+		KHR_VULKAN_ENABLE2_EXTENSION_NAME,
+		KHR_vulkan_enable2_SPEC_VERSION,
+	),
+	(
+		KHR_OPENGL_ENABLE_EXTENSION_NAME,
+		KHR_opengl_enable_SPEC_VERSION,
+	),
+	(MNDX_EGL_ENABLE_EXTENSION_NAME, MNDX_egl_enable_SPEC_VERSION),
+	(
+		KHR_COMPOSITION_LAYER_CYLINDER_EXTENSION_NAME,
+		KHR_composition_layer_cylinder_SPEC_VERSION,
+	),
+	(
+		KHR_COMPOSITION_LAYER_EQUIRECT2_EXTENSION_NAME,
+		KHR_composition_layer_equirect2_SPEC_VERSION,
+	),
+	(
+		KHR_COMPOSITION_LAYER_CUBE_EXTENSION_NAME,
+		KHR_composition_layer_cube_SPEC_VERSION,
+	),
+	(
+		KHR_COMPOSITION_LAYER_DEPTH_EXTENSION_NAME,
+		KHR_composition_layer_depth_SPEC_VERSION,
+	),
+];
 
 /// # Safety
 /// https://registry.khronos.org/OpenXR/specs/1.0/html/xrspec.html#xrEnumerateInstanceExtensionProperties
@@ -16,11 +57,11 @@ pub unsafe extern "system" fn xrEnumerateInstanceExtensionProperties(
 	items_ptr: *mut ExtensionProperties,
 ) -> openxr_sys::Result {
 	wrap_oxr! {
-		let mut headless = ExtensionProperties { ty: ExtensionProperties::TYPE, next: ptr::null_mut(), extension_name: [0; 128], extension_version: 2 };
-		copy_str_to_buffer(&String::from_utf8(MND_HEADLESS_EXTENSION_NAME.to_vec()).unwrap(), &mut headless.extension_name);
-		let extensions = [
-			headless
-		];
+		let extensions = EXTENSIONS.map(|(name, version)| {
+			let mut extension = ExtensionProperties { ty: ExtensionProperties::TYPE, next: ptr::null_mut(), extension_name: [0; 128], extension_version: version };
+			copy_str_to_buffer(CStr::from_bytes_with_nul(name).unwrap().to_str().unwrap(), &mut extension.extension_name);
+			extension
+		});
 		enumerate(input_count, output_count, items_ptr, &extensions)?;
 	}
 }
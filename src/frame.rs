@@ -0,0 +1,358 @@
+use crate::{
+	handle::Handle,
+	layer::{layers_from_frame_end_info, StardustLayer},
+	pose_cache::{PoseCache, StardustPoseSnapshot},
+	system::view_count,
+	util::{delay_from_time, now, time_from_delay},
+	XrResult,
+};
+use openxr_sys::{
+	Duration, FrameBeginInfo, FrameEndInfo, FrameState, FrameWaitInfo, Session, SessionState, Time,
+};
+use serde::{Deserialize, Serialize};
+
+/// Frame period used when there's no compositor to pace us, such as under XR_MND_headless.
+const HEADLESS_FRAME_PERIOD: i64 = 1_000_000_000 / 60;
+/// How many method timeouts `wait_frame` gets, since the server holds on to it until its next frame tick.
+const WAIT_FRAME_TIMEOUTS: u32 = 30;
+
+#[derive(Debug, Deserialize)]
+struct StardustFrameTiming {
+	/// Seconds from now until the frame will be displayed
+	predicted_display_delay: f64,
+	/// Seconds between displayed frames
+	predicted_display_period: f64,
+	should_render: bool,
+}
+
+/// Everything the app submitted in xrEndFrame, for the server to composite.
+#[derive(Debug, Serialize)]
+struct StardustFrame {
+	/// Seconds from now until the frame should be displayed
+	display_delay: f64,
+	environment_blend_mode: i32,
+	layers: Vec<StardustLayer>,
+}
+
+/// Bookkeeping for a session's frame loop, enforcing xrWaitFrame -> xrBeginFrame -> xrEndFrame order.
+pub struct FrameLoop {
+	headless: bool,
+	waited: bool,
+	begun: bool,
+	predicted_display_time: Time,
+}
+impl FrameLoop {
+	pub fn new(headless: bool) -> Self {
+		FrameLoop {
+			headless,
+			waited: false,
+			begun: false,
+			predicted_display_time: Time::from_nanos(0),
+		}
+	}
+	pub fn headless(&self) -> bool {
+		self.headless
+	}
+
+	/// When the next frame should start and be displayed, as nothing else is there to pace us.
+	fn headless_tick(&self) -> (Time, Time, Duration) {
+		let period = Duration::from_nanos(HEADLESS_FRAME_PERIOD);
+		let earliest_display_time = now().as_nanos() + period.as_nanos();
+		let mut display_time = self.predicted_display_time.as_nanos() + period.as_nanos();
+		if display_time < earliest_display_time {
+			// we fell behind, so skip ahead instead of bunching frames up
+			display_time = earliest_display_time;
+		}
+		let wake_time = display_time - period.as_nanos();
+		(
+			Time::from_nanos(wake_time),
+			Time::from_nanos(display_time),
+			period,
+		)
+	}
+	fn waited(&mut self, predicted_display_time: Time) {
+		self.waited = true;
+		self.predicted_display_time = predicted_display_time;
+	}
+	/// Returns `FRAME_DISCARDED` if the previous frame was begun but never ended.
+	fn begin(&mut self) -> Result<XrResult, XrResult> {
+		if !self.waited {
+			return Err(XrResult::ERROR_CALL_ORDER_INVALID);
+		}
+		self.waited = false;
+		if self.begun {
+			return Ok(XrResult::FRAME_DISCARDED);
+		}
+		self.begun = true;
+		Ok(XrResult::SUCCESS)
+	}
+	/// Whether there's a frame to end, without ending it, so xrEndFrame can check call order before validating what it was given.
+	fn can_end(&self) -> Result<(), XrResult> {
+		if !self.begun {
+			return Err(XrResult::ERROR_CALL_ORDER_INVALID);
+		}
+		Ok(())
+	}
+	fn end(&mut self) -> Result<(), XrResult> {
+		self.can_end()?;
+		self.begun = false;
+		Ok(())
+	}
+	/// Forget about any frame in flight, e.g. when the session ends.
+	pub fn reset(&mut self) {
+		self.waited = false;
+		self.begun = false;
+	}
+}
+
+/// # Safety
+/// https://www.khronos.org/registry/OpenXR/specs/1.0/html/xrspec.html#xrWaitFrame
+#[no_mangle]
+pub unsafe extern "system" fn xrWaitFrame(
+	session: Session,
+	_frame_wait_info: Option<&FrameWaitInfo>,
+	frame_state: &mut FrameState,
+) -> XrResult {
+	wrap_oxr! {
+		let stardust_session = session.get_stardust()?;
+		if !stardust_session.running() {
+			Err(XrResult::ERROR_SESSION_NOT_RUNNING)?;
+		}
+
+		let headless = stardust_session.frame_loop().headless();
+		let (predicted_display_time, predicted_display_period, server_should_render) = if headless {
+			let (wake_time, display_time, period) = stardust_session.frame_loop().headless_tick();
+			// other threads can still use the session while we sleep
+			let sleep_time = wake_time.as_nanos() - now().as_nanos();
+			if sleep_time > 0 {
+				std::thread::sleep(std::time::Duration::from_nanos(sleep_time as u64));
+			}
+			(display_time, period, true)
+		} else {
+			// the server holds on to this until its next frame tick, which can be a while when it throttles hidden sessions, but a server that never ticks again fails the call rather than hanging the app
+			let instance = stardust_session.instance()?;
+			let timing: StardustFrameTiming = instance
+				.execute_method_with_timeout(stardust_session.node_path(), "wait_frame", &(), Some(instance.method_timeout() * WAIT_FRAME_TIMEOUTS))?
+				.map_err(|_| XrResult::ERROR_RUNTIME_FAILURE)?;
+			(
+				time_from_delay(timing.predicted_display_delay),
+				Duration::from_nanos((timing.predicted_display_period * 1e9) as i64),
+				timing.should_render,
+			)
+		};
+		stardust_session.frame_loop().waited(predicted_display_time);
+		stardust_session.frame_synchronized();
+
+		// one round trip for everything the app locates this frame, from now through when the next frame's displayed
+		let mut times = vec![now(), predicted_display_time, Time::from_nanos(predicted_display_time.as_nanos() + predicted_display_period.as_nanos())];
+		times.sort_by_key(|t| t.as_nanos());
+		times.dedup_by_key(|t| t.as_nanos());
+		// the frame's already been waited on, so nothing here can fail the call, and without a snapshot locating just asks the server every time
+		let snapshot: Option<StardustPoseSnapshot> = stardust_session
+			.instance()
+			.ok()
+			.and_then(|instance| instance.execute_method(stardust_session.node_path(), "pose_snapshot", &PoseCache::sample_delays(&times)).ok())
+			.and_then(Result::ok);
+		let pose_cache = snapshot.and_then(|snapshot| PoseCache::new(times, stardust_session.view_configuration_type(), snapshot).ok());
+		stardust_session.set_pose_cache(pose_cache);
+
+		let state = stardust_session.state();
+		let visible = state == SessionState::VISIBLE || state == SessionState::FOCUSED;
+		frame_state.predicted_display_time = predicted_display_time;
+		frame_state.predicted_display_period = predicted_display_period;
+		frame_state.should_render = (server_should_render && visible).into();
+	}
+}
+
+/// # Safety
+/// https://www.khronos.org/registry/OpenXR/specs/1.0/html/xrspec.html#xrBeginFrame
+#[no_mangle]
+pub unsafe extern "system" fn xrBeginFrame(
+	session: Session,
+	_frame_begin_info: Option<&FrameBeginInfo>,
+) -> XrResult {
+	wrap_oxr! {
+		let stardust_session = session.get_stardust()?;
+		if !stardust_session.running() {
+			Err(XrResult::ERROR_SESSION_NOT_RUNNING)?;
+		}
+		let result = stardust_session.frame_loop().begin()?;
+		if result != XrResult::SUCCESS {
+			// FRAME_DISCARDED is a success code, but wrap_oxr! can only return those through Err
+			Err(result)?;
+		}
+	}
+}
+
+/// # Safety
+/// https://www.khronos.org/registry/OpenXR/specs/1.0/html/xrspec.html#xrEndFrame
+#[no_mangle]
+pub unsafe extern "system" fn xrEndFrame(
+	session: Session,
+	frame_end_info: &FrameEndInfo,
+) -> XrResult {
+	wrap_oxr! {
+		let stardust_session = session.get_stardust()?;
+		if !stardust_session.running() {
+			Err(XrResult::ERROR_SESSION_NOT_RUNNING)?;
+		}
+		// invalid frames are only worth reporting once the call's in order, and leave the frame begun so the app can fix them up
+		stardust_session.frame_loop().can_end()?;
+		if frame_end_info.display_time.as_nanos() <= 0 {
+			Err(XrResult::ERROR_TIME_INVALID)?;
+		}
+		if !stardust_session.environment_blend_modes().contains(&frame_end_info.environment_blend_mode) {
+			Err(XrResult::ERROR_ENVIRONMENT_BLEND_MODE_UNSUPPORTED)?;
+		}
+		let view_count = view_count(stardust_session.view_configuration_type())?;
+		let instance = stardust_session.instance()?;
+		let layers = layers_from_frame_end_info(&instance, frame_end_info, view_count, stardust_session.max_layer_count())?;
+		// checked again, as another thread could've ended the frame while we validated
+		stardust_session.frame_loop().end()?;
+
+		// headless sessions still submit, their layers just come from shared memory swapchains
+		instance.send_signal(stardust_session.node_path(), "end_frame", &StardustFrame {
+			display_delay: delay_from_time(frame_end_info.display_time),
+			environment_blend_mode: frame_end_info.environment_blend_mode.into_raw(),
+			layers,
+		})?;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::stub_server::{StubBehavior, StubSession};
+	use std::ptr;
+
+	#[test]
+	fn wait_begin_end() {
+		let mut frame_loop = FrameLoop::new(false);
+		for _ in 0..3 {
+			frame_loop.waited(now());
+			assert_eq!(frame_loop.begin(), Ok(XrResult::SUCCESS));
+			assert_eq!(frame_loop.end(), Ok(()));
+		}
+	}
+
+	#[test]
+	fn begin_without_end_discards() {
+		let mut frame_loop = FrameLoop::new(false);
+		frame_loop.waited(now());
+		assert_eq!(frame_loop.begin(), Ok(XrResult::SUCCESS));
+		frame_loop.waited(now());
+		assert_eq!(frame_loop.begin(), Ok(XrResult::FRAME_DISCARDED));
+		// the discarded frame's replaced, not stacked up
+		assert_eq!(frame_loop.end(), Ok(()));
+		assert_eq!(frame_loop.end(), Err(XrResult::ERROR_CALL_ORDER_INVALID));
+	}
+
+	#[test]
+	fn begin_without_wait() {
+		let mut frame_loop = FrameLoop::new(false);
+		assert_eq!(frame_loop.begin(), Err(XrResult::ERROR_CALL_ORDER_INVALID));
+		frame_loop.waited(now());
+		assert_eq!(frame_loop.begin(), Ok(XrResult::SUCCESS));
+		assert_eq!(frame_loop.begin(), Err(XrResult::ERROR_CALL_ORDER_INVALID));
+	}
+
+	#[test]
+	fn end_without_begin() {
+		let mut frame_loop = FrameLoop::new(false);
+		assert_eq!(frame_loop.end(), Err(XrResult::ERROR_CALL_ORDER_INVALID));
+		frame_loop.waited(now());
+		assert_eq!(frame_loop.end(), Err(XrResult::ERROR_CALL_ORDER_INVALID));
+	}
+
+	#[test]
+	fn checking_end_leaves_the_frame_begun() {
+		let mut frame_loop = FrameLoop::new(false);
+		assert_eq!(
+			frame_loop.can_end(),
+			Err(XrResult::ERROR_CALL_ORDER_INVALID)
+		);
+		frame_loop.waited(now());
+		assert_eq!(
+			frame_loop.can_end(),
+			Err(XrResult::ERROR_CALL_ORDER_INVALID)
+		);
+		assert_eq!(frame_loop.begin(), Ok(XrResult::SUCCESS));
+		// as xrEndFrame does before rejecting an invalid frame
+		assert_eq!(frame_loop.can_end(), Ok(()));
+		assert_eq!(frame_loop.can_end(), Ok(()));
+		assert_eq!(frame_loop.end(), Ok(()));
+		assert_eq!(
+			frame_loop.can_end(),
+			Err(XrResult::ERROR_CALL_ORDER_INVALID)
+		);
+	}
+
+	#[test]
+	fn reset_forgets_frame_in_flight() {
+		let mut frame_loop = FrameLoop::new(false);
+		frame_loop.waited(now());
+		assert_eq!(frame_loop.begin(), Ok(XrResult::SUCCESS));
+		frame_loop.reset();
+		assert_eq!(frame_loop.end(), Err(XrResult::ERROR_CALL_ORDER_INVALID));
+		assert_eq!(frame_loop.begin(), Err(XrResult::ERROR_CALL_ORDER_INVALID));
+	}
+
+	#[test]
+	fn headless_frames_are_a_period_apart() {
+		let mut frame_loop = FrameLoop::new(true);
+		assert!(frame_loop.headless());
+		let (wake_time, display_time, period) = frame_loop.headless_tick();
+		assert_eq!(period.as_nanos(), HEADLESS_FRAME_PERIOD);
+		assert_eq!(
+			display_time.as_nanos() - wake_time.as_nanos(),
+			HEADLESS_FRAME_PERIOD
+		);
+		assert!(display_time.as_nanos() >= now().as_nanos());
+
+		frame_loop.waited(display_time);
+		let (next_wake_time, next_display_time, _) = frame_loop.headless_tick();
+		assert_eq!(
+			next_display_time.as_nanos() - display_time.as_nanos(),
+			HEADLESS_FRAME_PERIOD
+		);
+		assert_eq!(next_wake_time.as_nanos(), display_time.as_nanos());
+	}
+
+	#[test]
+	fn headless_skips_ahead_when_behind() {
+		let mut frame_loop = FrameLoop::new(true);
+		// displayed long ago, as if the app stalled
+		frame_loop.waited(Time::from_nanos(1));
+		let before = now().as_nanos();
+		let (wake_time, display_time, _) = frame_loop.headless_tick();
+		assert!(display_time.as_nanos() >= before + HEADLESS_FRAME_PERIOD);
+		assert!(wake_time.as_nanos() >= before);
+	}
+
+	#[test]
+	fn waiting_survives_the_pose_snapshot_timing_out() {
+		let stub = StubSession::begin(
+			"waiting_survives_the_pose_snapshot_timing_out",
+			StubBehavior {
+				stall_pose_snapshot: true,
+			},
+		);
+		let mut frame_state = FrameState {
+			ty: FrameState::TYPE,
+			next: ptr::null_mut(),
+			predicted_display_time: Time::from_nanos(0),
+			predicted_display_period: Duration::from_nanos(0),
+			should_render: false.into(),
+		};
+		unsafe {
+			assert_eq!(
+				xrWaitFrame(stub.session, None, &mut frame_state),
+				XrResult::SUCCESS
+			);
+			assert!(frame_state.predicted_display_time.as_nanos() > 0);
+			assert!(stub.session.get_stardust().unwrap().pose_cache().is_none());
+			// the frame still counts as waited on
+			assert_eq!(xrBeginFrame(stub.session, None), XrResult::SUCCESS);
+		}
+	}
+}
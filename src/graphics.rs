@@ -0,0 +1,64 @@
+use crate::{
+	instance::StardustInstance,
+	opengl::OpenGLBinding,
+	util::{find_in_next_chain, lock},
+	vulkan::VulkanBinding,
+	XrResult,
+};
+use openxr_sys::{
+	BaseInStructure, GraphicsBindingEGLMNDX, GraphicsBindingVulkanKHR, StructureType,
+};
+use std::{ffi::c_void, sync::atomic::Ordering};
+
+/// The graphics API a session renders with, taken from the `XrGraphicsBinding*` in its create info.
+#[derive(Debug, Clone, Copy)]
+pub enum GraphicsBinding {
+	/// XR_MND_headless, no rendering at all
+	Headless,
+	Vulkan(VulkanBinding),
+	OpenGL(OpenGLBinding),
+}
+impl GraphicsBinding {
+	/// # Safety
+	/// `next` must be the next chain of a valid `XrSessionCreateInfo`
+	pub unsafe fn from_next_chain(
+		instance: &StardustInstance,
+		next: *const c_void,
+	) -> Result<Self, XrResult> {
+		let binding = if let Some(binding) = find_in_next_chain::<GraphicsBindingVulkanKHR>(
+			next,
+			StructureType::GRAPHICS_BINDING_VULKAN_KHR,
+		) {
+			GraphicsBinding::Vulkan(VulkanBinding::new(&lock(&instance.vulkan), binding)?)
+		} else if let Some(binding) = find_in_next_chain::<GraphicsBindingEGLMNDX>(
+			next,
+			StructureType::GRAPHICS_BINDING_EGL_MNDX,
+		) {
+			GraphicsBinding::OpenGL(OpenGLBinding::new_egl(binding)?)
+		} else if [
+			StructureType::GRAPHICS_BINDING_OPENGL_XLIB_KHR,
+			StructureType::GRAPHICS_BINDING_OPENGL_XCB_KHR,
+			StructureType::GRAPHICS_BINDING_OPENGL_WAYLAND_KHR,
+		]
+		.into_iter()
+		.any(|ty| find_in_next_chain::<BaseInStructure>(next, ty).is_some())
+		{
+			GraphicsBinding::OpenGL(OpenGLBinding::new()?)
+		} else if instance.extension_headless_enabled {
+			return Ok(GraphicsBinding::Headless);
+		} else {
+			return Err(XrResult::ERROR_GRAPHICS_DEVICE_INVALID);
+		};
+
+		if !instance
+			.graphics_requirements_queried
+			.load(Ordering::Acquire)
+		{
+			return Err(XrResult::ERROR_GRAPHICS_REQUIREMENTS_CALL_MISSING);
+		}
+		Ok(binding)
+	}
+	pub fn is_headless(&self) -> bool {
+		matches!(self, GraphicsBinding::Headless)
+	}
+}
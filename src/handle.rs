@@ -0,0 +1,174 @@
+use crate::{
+	input::{StardustAction, StardustActionSet},
+	instance::StardustInstance,
+	session::StardustSession,
+	space::StardustSpace,
+	swapchain::StardustSwapchain,
+	util::lock,
+	XrResult,
+};
+use slotmap::{DefaultKey, Key, KeyData, SecondaryMap, SlotMap};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+
+/// Which table a handle's object lives in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandleType {
+	Instance,
+	Session,
+	Space,
+	Swapchain,
+	ActionSet,
+	Action,
+}
+
+/// An object behind a handle, most of which stand for a node on the server.
+pub trait Node: Send + Sync {
+	/// The node the server made for the object, to destroy along with its handle.
+	fn server_node_path(&self) -> Option<&str> {
+		None
+	}
+}
+
+/// Where a handle sits in the tree of handles, so destroying one takes everything made from it along.
+struct HandleInfo {
+	ty: HandleType,
+	parent: Option<DefaultKey>,
+	children: Vec<DefaultKey>,
+}
+
+/// Every live handle the app has, each key allocated once across all types so a handle of one type is never valid as another.
+///
+/// Objects are shared so a call still using one keeps it alive when another thread destroys its handle.
+#[derive(Default)]
+pub struct Handles {
+	keys: SlotMap<DefaultKey, HandleInfo>,
+	pub instances: SecondaryMap<DefaultKey, Arc<StardustInstance>>,
+	pub sessions: SecondaryMap<DefaultKey, Arc<StardustSession>>,
+	pub spaces: SecondaryMap<DefaultKey, Arc<StardustSpace>>,
+	pub swapchains: SecondaryMap<DefaultKey, Arc<StardustSwapchain>>,
+	pub action_sets: SecondaryMap<DefaultKey, Arc<StardustActionSet>>,
+	pub actions: SecondaryMap<DefaultKey, Arc<StardustAction>>,
+}
+impl Handles {
+	fn get() -> MutexGuard<'static, Handles> {
+		static HANDLES: OnceLock<Mutex<Handles>> = OnceLock::new();
+		lock(HANDLES.get_or_init(Mutex::default))
+	}
+
+	fn remove_object(&mut self, key: DefaultKey, ty: HandleType) -> Option<Arc<dyn Node>> {
+		match ty {
+			HandleType::Instance => self.instances.remove(key).map(|o| o as _),
+			HandleType::Session => self.sessions.remove(key).map(|o| o as _),
+			HandleType::Space => self.spaces.remove(key).map(|o| o as _),
+			HandleType::Swapchain => self.swapchains.remove(key).map(|o| o as _),
+			HandleType::ActionSet => self.action_sets.remove(key).map(|o| o as _),
+			HandleType::Action => self.actions.remove(key).map(|o| o as _),
+		}
+	}
+	/// Take a handle and all its descendants out of the tables, children before their parents.
+	fn remove_tree(&mut self, key: DefaultKey, objects: &mut Vec<Arc<dyn Node>>) {
+		let Some(info) = self.keys.remove(key) else {
+			return;
+		};
+		for child in info.children {
+			self.remove_tree(child, objects);
+		}
+		objects.extend(self.remove_object(key, info.ty));
+	}
+	/// The instance a handle was made from, through which its nodes are destroyed.
+	fn root_instance(&self, mut key: DefaultKey) -> Option<Arc<StardustInstance>> {
+		while let Some(parent) = self.keys.get(key)?.parent {
+			key = parent;
+		}
+		self.instances.get(key).cloned()
+	}
+}
+
+pub trait Handle: Sized {
+	type StardustType: Node + 'static;
+	const TYPE: HandleType;
+
+	fn raw(&self) -> u64;
+	fn from_raw(raw: u64) -> Self;
+	fn table(handles: &mut Handles) -> &mut SecondaryMap<DefaultKey, Arc<Self::StardustType>>;
+
+	fn key(&self) -> DefaultKey {
+		KeyData::from_ffi(self.raw()).into()
+	}
+	/// Make a handle for a new object with nothing above it, which is only instances.
+	fn create(stardust: Self::StardustType) -> Self {
+		let mut handles = Handles::get();
+		let key = handles.keys.insert(HandleInfo {
+			ty: Self::TYPE,
+			parent: None,
+			children: Vec::new(),
+		});
+		Self::table(&mut handles).insert(key, Arc::new(stardust));
+		Self::from_raw(key.data().as_ffi())
+	}
+	/// Make a handle for a new object, to be destroyed along with `parent`.
+	fn create_child<P: Handle>(stardust: Self::StardustType, parent: &P) -> Result<Self, XrResult> {
+		let mut handles = Handles::get();
+		let parent_key = parent.key();
+		if !P::table(&mut handles).contains_key(parent_key) {
+			return Err(XrResult::ERROR_HANDLE_INVALID);
+		}
+		let key = handles.keys.insert(HandleInfo {
+			ty: Self::TYPE,
+			parent: Some(parent_key),
+			children: Vec::new(),
+		});
+		handles.keys[parent_key].children.push(key);
+		Self::table(&mut handles).insert(key, Arc::new(stardust));
+		Ok(Self::from_raw(key.data().as_ffi()))
+	}
+	/// Fails with ERROR_INSTANCE_LOST or ERROR_SESSION_LOST once the object can't be used anymore, having gone with the server.
+	fn check_lost(_stardust: &Self::StardustType) -> Result<(), XrResult> {
+		Ok(())
+	}
+	/// The object behind the handle, as long as it's a live handle of this type that hasn't been lost.
+	fn get_stardust(&self) -> Result<Arc<Self::StardustType>, XrResult> {
+		let stardust = self.get_stardust_even_if_lost()?;
+		Self::check_lost(&stardust)?;
+		Ok(stardust)
+	}
+	/// Like [`Self::get_stardust`], for the few calls that have to keep working once it's lost, like destroying it.
+	fn get_stardust_even_if_lost(&self) -> Result<Arc<Self::StardustType>, XrResult> {
+		Self::table(&mut Handles::get())
+			.get(self.key())
+			.cloned()
+			.ok_or(XrResult::ERROR_HANDLE_INVALID)
+	}
+	/// Destroy the object and every handle made from it, along with their nodes on the server.
+	fn destroy(self) -> Result<(), XrResult> {
+		let key = self.key();
+		let mut objects = Vec::new();
+		let instance = {
+			let mut handles = Handles::get();
+			if !Self::table(&mut handles).contains_key(key) {
+				return Err(XrResult::ERROR_HANDLE_INVALID);
+			}
+			let instance = handles.root_instance(key);
+			if let Some(parent) = handles.keys[key].parent {
+				if let Some(parent) = handles.keys.get_mut(parent) {
+					parent.children.retain(|child| *child != key);
+				}
+			}
+			handles.remove_tree(key, &mut objects);
+			instance
+		};
+		// children before their parents, and while they're all still alive
+		if let Some(instance) = instance.filter(|instance| !instance.lost()) {
+			for node_path in objects
+				.iter()
+				.filter_map(|object| object.server_node_path())
+			{
+				// if the connection's gone mid way, so are the nodes
+				let _ = instance.send_signal(node_path, "destroy", &());
+			}
+		}
+		// released outside the lock, so any cleanup that goes through handles can't deadlock on it
+		drop(objects);
+		Ok(())
+	}
+}
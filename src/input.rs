@@ -1,6 +1,7 @@
 use crate::{
+	handle::{Handle, HandleType, Handles, Node},
 	instance::StardustInstance,
-	util::{str_from_const_char, Handle},
+	util::str_from_const_char,
 	XrResult,
 };
 use openxr_sys::{
@@ -10,14 +11,27 @@ use openxr_sys::{
 	InputSourceLocalizedNameGetInfo, Instance, InteractionProfileState,
 	InteractionProfileSuggestedBinding, Path, Session, SessionActionSetsAttachInfo,
 };
-use std::{ffi::c_char, ptr::slice_from_raw_parts};
+use slotmap::{DefaultKey, SecondaryMap};
+use std::{ffi::c_char, ptr::slice_from_raw_parts, sync::Arc};
 
 impl Handle for ActionSet {
 	type StardustType = StardustActionSet;
+	const TYPE: HandleType = HandleType::ActionSet;
 
 	fn raw(&self) -> u64 {
 		self.into_raw()
 	}
+	fn from_raw(raw: u64) -> Self {
+		ActionSet::from_raw(raw)
+	}
+	fn table(handles: &mut Handles) -> &mut SecondaryMap<DefaultKey, Arc<StardustActionSet>> {
+		&mut handles.action_sets
+	}
+}
+impl Node for StardustActionSet {
+	fn server_node_path(&self) -> Option<&str> {
+		Some(&self.node_path)
+	}
 }
 
 pub struct StardustActionSet {
@@ -41,7 +55,7 @@ impl StardustActionSet {
 		};
 		Ok(action_set)
 	}
-	pub fn instance<'a>(&'a mut self) -> Result<&'a mut StardustInstance, XrResult> {
+	pub fn instance(&self) -> Result<Arc<StardustInstance>, XrResult> {
 		self.instance.get_stardust()
 	}
 	pub fn node_path(&self) -> &str {
@@ -60,8 +74,7 @@ pub unsafe extern "system" fn xrCreateActionSet(
 	wrap_oxr! {
 		// let next_chain = get_next_chain(create_info);
 
-		let stardust_action_set = Box::new(StardustActionSet::new(instance, create_info)?);
-		*action_set = ActionSet::from_raw(Box::into_raw(stardust_action_set) as u64);
+		*action_set = Handle::create_child(StardustActionSet::new(instance, create_info)?, &instance)?;
 	}
 }
 
@@ -153,10 +166,22 @@ pub unsafe extern "system" fn xrGetActionStatePose(
 
 impl Handle for Action {
 	type StardustType = StardustAction;
+	const TYPE: HandleType = HandleType::Action;
 
 	fn raw(&self) -> u64 {
 		self.into_raw()
 	}
+	fn from_raw(raw: u64) -> Self {
+		Action::from_raw(raw)
+	}
+	fn table(handles: &mut Handles) -> &mut SecondaryMap<DefaultKey, Arc<StardustAction>> {
+		&mut handles.actions
+	}
+}
+impl Node for StardustAction {
+	fn server_node_path(&self) -> Option<&str> {
+		Some(&self.node_path)
+	}
 }
 
 pub struct StardustAction {
@@ -182,7 +207,7 @@ impl StardustAction {
 		};
 		Ok(action)
 	}
-	pub fn action_set<'a>(&'a mut self) -> Result<&'a mut StardustActionSet, XrResult> {
+	pub fn action_set(&self) -> Result<Arc<StardustActionSet>, XrResult> {
 		self.action_set.get_stardust()
 	}
 	pub fn node_path(&self) -> &str {
@@ -201,8 +226,7 @@ pub unsafe extern "system" fn xrCreateAction(
 	wrap_oxr! {
 		// let next_chain = get_next_chain(create_info);
 
-		let stardust_action = Box::new(StardustAction::new(action_set, create_info)?);
-		*action = Action::from_raw(Box::into_raw(stardust_action) as u64);
+		*action = Handle::create_child(StardustAction::new(action_set, create_info)?, &action_set)?;
 	}
 }
 
@@ -1,5 +1,8 @@
 use crate::{
+	events::{xrPollEvent, EventQueue, ServerEvent, StardustScenegraph},
 	extensions::xrEnumerateInstanceExtensionProperties,
+	frame::{xrBeginFrame, xrEndFrame, xrWaitFrame},
+	handle::{Handle, HandleType, Handles, Node},
 	input::{
 		xrApplyHapticFeedback, xrAttachSessionActionSets, xrCreateAction, xrCreateActionSet,
 		xrDestroyAction, xrDestroyActionSet, xrEnumerateBoundSourcesForAction,
@@ -7,43 +10,55 @@ use crate::{
 		xrGetActionStateVector2f, xrGetCurrentInteractionProfile, xrGetInputSourceLocalizedName,
 		xrStopHapticFeedback, xrSuggestInteractionProfileBindings, xrSyncActions,
 	},
-	session::{xrCreateSession, xrDestroySession},
+	ipc::{FdChannel, Ipc, IpcError},
+	opengl::xrGetOpenGLGraphicsRequirementsKHR,
+	session::{
+		xrBeginSession, xrCreateSession, xrDestroySession, xrEndSession, xrRequestExitSession,
+		StardustSession,
+	},
+	space::{
+		xrCreateReferenceSpace, xrDestroySpace, xrEnumerateReferenceSpaces, xrLocateSpace,
+		xrLocateViews,
+	},
 	string::{xrPathToString, xrResultToString, xrStringToPath, xrStructureTypeToString},
+	swapchain::{
+		xrAcquireSwapchainImage, xrCreateSwapchain, xrDestroySwapchain,
+		xrEnumerateSwapchainFormats, xrEnumerateSwapchainImages, xrReleaseSwapchainImage,
+		xrWaitSwapchainImage,
+	},
 	system::{
 		xrEnumerateEnvironmentBlendModes, xrEnumerateViewConfigurationViews,
 		xrEnumerateViewConfigurations, xrGetSystem, xrGetSystemProperties,
 		xrGetViewConfigurationProperties,
 	},
-	util::{copy_str_to_buffer, str_from_const_char, Handle},
+	util::{copy_str_to_buffer, lock, now, str_from_const_char},
+	vulkan::{
+		xrCreateVulkanDeviceKHR, xrCreateVulkanInstanceKHR, xrGetVulkanGraphicsDevice2KHR,
+		xrGetVulkanGraphicsRequirements2KHR, VulkanContext,
+	},
 	wip::*,
 	xrEnumerateApiLayerProperties, XrResult,
 };
 use openxr_sys::{
-	pfn::VoidFunction, Instance, InstanceCreateInfo, InstanceProperties, Path, StructureType,
+	pfn::VoidFunction, EventDataBuffer, EventDataInstanceLossPending,
+	EventDataInteractionProfileChanged, EventDataReferenceSpaceChangePending, Instance,
+	InstanceCreateInfo, InstanceProperties, Path, Posef, Session, SessionState, StructureType,
 	Version,
 };
+use rustc_hash::FxHashMap;
 use serde::{de::DeserializeOwned, Serialize};
-use slotmap::{DefaultKey, KeyData, SlotMap};
-use stardust_xr::{
-	client,
-	messenger::{self, MessageSender},
-	scenegraph::{Scenegraph, ScenegraphError},
-	schemas::flex::{deserialize, serialize},
+use slotmap::{DefaultKey, KeyData, SecondaryMap, SlotMap};
+use stardust_xr::schemas::flex::{deserialize, serialize};
+use std::{
+	os::fd::BorrowedFd,
+	ptr::{self, slice_from_raw_parts},
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		mpsc::{self, Receiver},
+		Arc, Mutex, OnceLock,
+	},
+	time::Duration,
 };
-use std::ptr::slice_from_raw_parts;
-use tokio::runtime::Runtime;
-
-struct DummyScenegraph;
-impl Scenegraph for DummyScenegraph {
-	fn execute_method(
-		&self,
-		_path: &str,
-		_method: &str,
-		_data: &[u8],
-	) -> Result<Vec<u8>, stardust_xr::scenegraph::ScenegraphError> {
-		Err(ScenegraphError::NodeNotFound)
-	}
-}
 
 #[derive(Default, Serialize)]
 struct SetupInfo {
@@ -61,44 +76,118 @@ struct ApplicationInfo {
 
 impl Handle for Instance {
 	type StardustType = StardustInstance;
+	const TYPE: HandleType = HandleType::Instance;
 
 	fn raw(&self) -> u64 {
 		self.into_raw()
 	}
+	fn from_raw(raw: u64) -> Self {
+		Instance::from_raw(raw)
+	}
+	fn table(handles: &mut Handles) -> &mut SecondaryMap<DefaultKey, Arc<StardustInstance>> {
+		&mut handles.instances
+	}
+	fn check_lost(stardust: &StardustInstance) -> Result<(), XrResult> {
+		if stardust.lost() {
+			return Err(XrResult::ERROR_INSTANCE_LOST);
+		}
+		Ok(())
+	}
 }
+/// Everything the instance made goes away with the connection.
+impl Node for StardustInstance {}
 
+/// Shared between every thread the app calls in from, so everything mutable is behind a lock.
+///
+/// Locks are only held for as long as it takes to read or update the state behind them, never across a call to the server or into another object's locks, so they can't deadlock.
+/// Sessions hold their lifecycle lock across the signals that go with a state change, so they reach the server in the same order, but signals are only queued for the IPC thread and never wait on it.
+/// The event queue's the one lock taken while holding another, as sessions queue their state changes while their lifecycle's locked, but nothing else is ever locked while holding it.
 pub struct StardustInstance {
-	runtime: Runtime,
-	message_sender: MessageSender,
-	pub paths: SlotMap<DefaultKey, String>,
+	/// Once the server goes away every call that needs it fails with ERROR_INSTANCE_LOST, until the app recreates the instance
+	ipc: Ipc,
+	/// Connected the first time there are fds to send, as headless apps that never make a swapchain don't need it
+	fd_channel: OnceLock<FdChannel>,
+	server_events: Mutex<Receiver<ServerEvent>>,
+	/// Shared with every session, so their state changes are queued in the order they happen
+	events: Arc<Mutex<EventQueue>>,
+	/// Whether the app's been told the server went away yet
+	loss_reported: AtomicBool,
+	/// Sessions by their node path, so server signals can find them
+	pub sessions: Mutex<FxHashMap<String, Session>>,
+	pub paths: Mutex<SlotMap<DefaultKey, String>>,
 	pub extension_headless_enabled: bool,
+	pub extension_vulkan_enable2_enabled: bool,
+	pub extension_opengl_enable_enabled: bool,
+	pub extension_composition_layer_cylinder_enabled: bool,
+	pub extension_composition_layer_equirect2_enabled: bool,
+	pub extension_composition_layer_cube_enabled: bool,
+	pub extension_composition_layer_depth_enabled: bool,
+	pub graphics_requirements_queried: AtomicBool,
+	pub vulkan: Mutex<VulkanContext>,
 }
 impl StardustInstance {
 	fn new(info: &SetupInfo) -> Result<Self, XrResult> {
-		let runtime = tokio::runtime::Builder::new_current_thread()
-			.enable_io()
-			.enable_time()
-			.build()
-			.map_err(|_| XrResult::ERROR_RUNTIME_UNAVAILABLE)?;
-		let client = runtime
-			.block_on(client::connect())
+		let (server_event_sender, server_events) = mpsc::channel();
+		let ipc = Ipc::connect(StardustScenegraph::new(server_event_sender))
 			.map_err(|_| XrResult::ERROR_RUNTIME_UNAVAILABLE)?;
-		let (message_sender, mut message_receiver) = messenger::create(client);
-		runtime.spawn(
-			async move { while message_receiver.dispatch(&DummyScenegraph).await.is_ok() {} },
-		);
 
-		let mut instance = StardustInstance {
-			runtime,
-			message_sender,
-			paths: SlotMap::default(),
+		let instance = StardustInstance {
+			ipc,
+			fd_channel: OnceLock::new(),
+			server_events: Mutex::new(server_events),
+			events: Arc::default(),
+			loss_reported: AtomicBool::new(false),
+			sessions: Mutex::default(),
+			paths: Mutex::default(),
 			extension_headless_enabled: info.extension_names.iter().any(|n| n == "XR_MND_headless"),
+			extension_vulkan_enable2_enabled: info
+				.extension_names
+				.iter()
+				.any(|n| n == "XR_KHR_vulkan_enable2"),
+			extension_opengl_enable_enabled: info
+				.extension_names
+				.iter()
+				.any(|n| n == "XR_KHR_opengl_enable"),
+			extension_composition_layer_cylinder_enabled: info
+				.extension_names
+				.iter()
+				.any(|n| n == "XR_KHR_composition_layer_cylinder"),
+			extension_composition_layer_equirect2_enabled: info
+				.extension_names
+				.iter()
+				.any(|n| n == "XR_KHR_composition_layer_equirect2"),
+			extension_composition_layer_cube_enabled: info
+				.extension_names
+				.iter()
+				.any(|n| n == "XR_KHR_composition_layer_cube"),
+			extension_composition_layer_depth_enabled: info
+				.extension_names
+				.iter()
+				.any(|n| n == "XR_KHR_composition_layer_depth"),
+			graphics_requirements_queried: AtomicBool::new(false),
+			vulkan: Mutex::default(),
 		};
 		instance.send_signal("/openxr", "setup_instance", &info)?;
 
 		Ok(instance)
 	}
 	pub fn get_proc_addr(&self, name: &str) -> Result<VoidFunction, XrResult> {
+		if self.extension_vulkan_enable2_enabled {
+			if let Ok(function) = oxr_fns![
+				name,
+				xrCreateVulkanInstanceKHR,
+				xrCreateVulkanDeviceKHR,
+				xrGetVulkanGraphicsDevice2KHR,
+				xrGetVulkanGraphicsRequirements2KHR
+			] {
+				return Ok(function);
+			}
+		}
+		if self.extension_opengl_enable_enabled {
+			if let Ok(function) = oxr_fns![name, xrGetOpenGLGraphicsRequirementsKHR] {
+				return Ok(function);
+			}
+		}
 		oxr_fns![
 			name,
 			xrEnumerateInstanceExtensionProperties,
@@ -158,45 +247,173 @@ impl StardustInstance {
 			xrGetInputSourceLocalizedName
 		]
 	}
+	/// Queue a signal for the IO thread to send, without waiting on anything.
 	pub fn send_signal<S: Serialize>(
-		&mut self,
+		&self,
 		node_path: &str,
 		signal_name: &str,
 		data: &S,
 	) -> Result<(), XrResult> {
-		let serialized_data = serialize(data).map_err(|_| XrResult::ERROR_RUNTIME_FAILURE)?;
-		let signal_future = self
-			.message_sender
-			.signal(node_path, signal_name, &serialized_data);
-		self.runtime
-			.block_on(signal_future)
-			.map_err(|_| XrResult::ERROR_RUNTIME_FAILURE)
+		let serialized_data = serialize(data).map_err(|_| IpcError::Serialization)?;
+		Ok(self.ipc.signal(node_path, signal_name, serialized_data)?)
 	}
+	/// Call a method on the server and block until it answers, which any number of threads can do at once.
 	pub fn execute_method<S: Serialize, D: DeserializeOwned>(
-		&mut self,
+		&self,
 		node_path: &str,
 		method_name: &str,
 		send_data: &S,
 	) -> Result<anyhow::Result<D>, XrResult> {
-		let send_data = serialize(send_data).map_err(|_| XrResult::ERROR_RUNTIME_FAILURE)?;
-		let execute_method_future = self
-			.message_sender
-			.method(node_path, method_name, &send_data);
+		Ok(self.execute_method_with_timeout(
+			node_path,
+			method_name,
+			send_data,
+			Some(self.ipc.method_timeout()),
+		)?)
+	}
+	/// Like [`Self::execute_method`], for methods the server may take longer to answer on purpose, with `None` waiting forever.
+	///
+	/// Leaves the [`IpcError`] for callers that can tell the app it timed out.
+	pub fn execute_method_with_timeout<S: Serialize, D: DeserializeOwned>(
+		&self,
+		node_path: &str,
+		method_name: &str,
+		send_data: &S,
+		timeout: Option<Duration>,
+	) -> Result<anyhow::Result<D>, IpcError> {
+		let send_data = serialize(send_data).map_err(|_| IpcError::Serialization)?;
+		let data = self
+			.ipc
+			.method(node_path, method_name, send_data, timeout)?;
+		Ok(data.and_then(|data| Ok(deserialize(&data)?)))
+	}
+	/// Send the fds behind swapchain `id`'s images to the server, in image order.
+	///
+	/// Anything that stops them getting there is logged and fails with RUNTIME_FAILURE, as the swapchain would be no use to the server without them.
+	pub fn send_fds(&self, id: &str, fds: &[BorrowedFd]) -> Result<(), XrResult> {
+		let fd_channel = match self.fd_channel.get() {
+			Some(fd_channel) => fd_channel,
+			None => {
+				let path: String =
+					self.execute_method("/openxr", "fd_socket", &())?
+						.map_err(|e| {
+							eprintln!(
+								"Stardust server has no socket to send swapchain memory over: {e}"
+							);
+							XrResult::ERROR_RUNTIME_FAILURE
+						})?;
+				let fd_channel = FdChannel::connect(&path).map_err(|e| {
+					eprintln!("Couldn't connect to the Stardust server's fd socket at {path}: {e}");
+					XrResult::ERROR_RUNTIME_FAILURE
+				})?;
+				// another thread may have beaten us to it, in which case theirs is used and ours closed
+				self.fd_channel.get_or_init(|| fd_channel)
+			}
+		};
+		fd_channel.send(id, fds).map_err(|e| {
+			eprintln!("Couldn't send swapchain memory to the Stardust server: {e}");
+			XrResult::ERROR_RUNTIME_FAILURE
+		})
+	}
+	/// The queue `xrPollEvent` pops from, for sessions to push their state changes onto.
+	pub fn events(&self) -> Arc<Mutex<EventQueue>> {
+		self.events.clone()
+	}
+	/// Whether the connection to the server is gone, even if the app hasn't been told yet.
+	pub fn lost(&self) -> bool {
+		self.ipc.lost()
+	}
+	/// How long methods wait on the server by default.
+	pub fn method_timeout(&self) -> Duration {
+		self.ipc.method_timeout()
+	}
+	/// The server's gone along with everything we made on it, so the app has to start over with a new instance.
+	///
+	/// That's also how we reconnect, as the new instance connects to whichever server is running by then.
+	fn report_loss(&self) {
+		if !self.ipc.lost() || self.loss_reported.swap(true, Ordering::AcqRel) {
+			return;
+		}
+		eprintln!("Lost connection to the Stardust server");
+		lock(&self.events).push(EventDataInstanceLossPending {
+			ty: EventDataInstanceLossPending::TYPE,
+			next: ptr::null(),
+			loss_time: now(),
+		});
+		for stardust_session in self.stardust_sessions() {
+			stardust_session.server_state_changed(SessionState::LOSS_PENDING);
+		}
+	}
 
-		let future = async move {
-			let timeout = tokio::time::sleep(core::time::Duration::from_secs(1));
-			Ok(tokio::select! {
-				_ = timeout => return Err(XrResult::ERROR_RUNTIME_FAILURE),
-				d = execute_method_future => {
-					let data = d.map_err(|_| XrResult::ERROR_RUNTIME_FAILURE)?;
-					data.and_then(|data| Ok(deserialize(&data)?))
+	/// Apply everything the server has told us since the last call and pop the oldest event.
+	pub fn poll_event(&self) -> Option<EventDataBuffer> {
+		let server_events: Vec<ServerEvent> = lock(&self.server_events).try_iter().collect();
+		for event in server_events {
+			self.apply_server_event(event);
+		}
+		self.report_loss();
+		lock(&self.events).pop()
+	}
+	fn apply_server_event(&self, event: ServerEvent) {
+		match event {
+			ServerEvent::InstanceLossPending { loss_time } => {
+				lock(&self.events).push(EventDataInstanceLossPending {
+					ty: EventDataInstanceLossPending::TYPE,
+					next: ptr::null(),
+					loss_time,
+				})
+			}
+			ServerEvent::SessionStateChanged {
+				session_path,
+				state,
+			} => {
+				if let Some(session) = self.session_from_path(&session_path) {
+					session.server_state_changed(state);
 				}
-			})
-		};
-		self.runtime.block_on(future)
+			}
+			ServerEvent::ReferenceSpaceChangePending {
+				session_path,
+				reference_space_type,
+				change_time,
+				pose_in_previous_space,
+			} => {
+				if let Some(session) = lock(&self.sessions).get(&session_path).copied() {
+					lock(&self.events).push(EventDataReferenceSpaceChangePending {
+						ty: EventDataReferenceSpaceChangePending::TYPE,
+						next: ptr::null(),
+						session,
+						reference_space_type,
+						change_time,
+						pose_valid: pose_in_previous_space.is_some().into(),
+						pose_in_previous_space: pose_in_previous_space.unwrap_or(Posef::IDENTITY),
+					})
+				}
+			}
+			ServerEvent::InteractionProfileChanged { session_path } => {
+				if let Some(session) = lock(&self.sessions).get(&session_path).copied() {
+					lock(&self.events).push(EventDataInteractionProfileChanged {
+						ty: EventDataInteractionProfileChanged::TYPE,
+						next: ptr::null(),
+						session,
+					})
+				}
+			}
+		}
+	}
+	fn session_from_path(&self, node_path: &str) -> Option<Arc<StardustSession>> {
+		let session = lock(&self.sessions).get(node_path).copied()?;
+		session.get_stardust_even_if_lost().ok()
 	}
+	fn stardust_sessions(&self) -> Vec<Arc<StardustSession>> {
+		let sessions: Vec<Session> = lock(&self.sessions).values().copied().collect();
+		sessions
+			.into_iter()
+			.filter_map(|session| session.get_stardust_even_if_lost().ok())
+			.collect()
+	}
+
 	pub fn path(&self, path: Path) -> Result<String, XrResult> {
-		self.paths
+		lock(&self.paths)
 			.get(DefaultKey::from(KeyData::from_ffi(path.into_raw())))
 			.cloned()
 			.ok_or(XrResult::ERROR_PATH_INVALID)
@@ -233,8 +450,7 @@ pub unsafe extern "system" fn xrCreateInstance(
 			extension_names,
 		};
 
-		let stardust_instance = Box::new(StardustInstance::new(&info)?);
-		*instance = Instance::from_raw(Box::into_raw(stardust_instance) as u64);
+		*instance = Handle::create(StardustInstance::new(&info)?);
 	}
 }
 
@@ -0,0 +1,443 @@
+use crate::{events::StardustScenegraph, XrResult};
+use rustc_hash::FxHashMap;
+use stardust_xr::{
+	client,
+	messenger::{self, MessageSenderHandle, MessengerError},
+};
+use std::{
+	ffi::c_void,
+	future::Future,
+	io,
+	mem::{size_of, size_of_val, zeroed},
+	os::fd::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+	ptr,
+	sync::{
+		atomic::{AtomicBool, AtomicU64, Ordering},
+		mpsc::{self, SyncSender},
+		Arc,
+	},
+	time::Duration,
+};
+use tokio::{
+	runtime::Runtime,
+	sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+};
+
+/// How long to wait on the server to answer a method before assuming it's hung, unless overridden.
+const DEFAULT_METHOD_TIMEOUT: Duration = Duration::from_secs(1);
+/// Milliseconds to use instead of [`DEFAULT_METHOD_TIMEOUT`], for slow servers or debugging one.
+const METHOD_TIMEOUT_VAR: &str = "STARDUST_OPENXR_METHOD_TIMEOUT_MS";
+
+/// Why a call to the server didn't get an answer, as opposed to the server answering with an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpcError {
+	/// The connection closed, most likely because the server quit or crashed
+	Disconnected,
+	/// The server took longer than the method's timeout to answer
+	TimedOut,
+	/// The request couldn't be serialized
+	Serialization,
+}
+/// Timing out is only RUNTIME_FAILURE for calls that can't return TIMEOUT_EXPIRED, which is why it's logged, callers that can should map it themselves.
+impl From<IpcError> for XrResult {
+	fn from(error: IpcError) -> Self {
+		match error {
+			IpcError::Disconnected => XrResult::ERROR_INSTANCE_LOST,
+			IpcError::Serialization => XrResult::ERROR_VALIDATION_FAILURE,
+			IpcError::TimedOut => XrResult::ERROR_RUNTIME_FAILURE,
+		}
+	}
+}
+
+type MethodReply = SyncSender<Result<anyhow::Result<Vec<u8>>, IpcError>>;
+
+/// Work for the IO thread, which is the only thing that touches the messenger.
+enum Request {
+	Signal {
+		node_path: String,
+		signal_name: String,
+		data: Vec<u8>,
+	},
+	Method {
+		id: u64,
+		node_path: String,
+		method_name: String,
+		data: Vec<u8>,
+		timeout: Option<Duration>,
+		reply: MethodReply,
+	},
+	/// The server answered the method with this id, or didn't in time
+	Answer {
+		id: u64,
+		answer: Result<anyhow::Result<Vec<u8>>, IpcError>,
+	},
+	/// The socket closed, so nothing pending will ever be answered
+	Disconnected,
+}
+
+/// Our connection to the Stardust server, with all socket IO on a dedicated thread.
+///
+/// Signals are queued and return straight away, so only methods ever block the app, and only the thread that called them.
+pub struct Ipc {
+	/// Owns the IO thread, which shuts down along with it
+	_runtime: Runtime,
+	requests: UnboundedSender<Request>,
+	next_method_id: AtomicU64,
+	lost: Arc<AtomicBool>,
+	method_timeout: Duration,
+}
+impl Ipc {
+	/// Connect to the server, dispatching its signals to `scenegraph`.
+	pub fn connect(scenegraph: StardustScenegraph) -> Result<Self, IpcError> {
+		let method_timeout = std::env::var(METHOD_TIMEOUT_VAR)
+			.ok()
+			.and_then(|millis| millis.parse().ok())
+			.map(Duration::from_millis)
+			.unwrap_or(DEFAULT_METHOD_TIMEOUT);
+		// one worker is plenty for a single socket, and keeps all IO off the app's threads
+		let runtime = tokio::runtime::Builder::new_multi_thread()
+			.worker_threads(1)
+			.thread_name("stardust-io")
+			.enable_io()
+			.enable_time()
+			.build()
+			.map_err(|_| IpcError::Disconnected)?;
+		let connection = wait_on(&runtime, client::connect(), method_timeout)
+			.ok_or(IpcError::TimedOut)?
+			.map_err(|_| IpcError::Disconnected)?;
+		let (mut message_sender, mut message_receiver) = messenger::create(connection);
+		let message_sender_handle = message_sender.handle();
+		let (requests, request_receiver) = unbounded_channel();
+		let lost = Arc::new(AtomicBool::new(false));
+
+		let flush_requests = requests.clone();
+		let flush_lost = lost.clone();
+		runtime.spawn(async move {
+			let _ = message_sender.flush().await;
+			flush_lost.store(true, Ordering::Release);
+			let _ = flush_requests.send(Request::Disconnected);
+		});
+		let dispatch_requests = requests.clone();
+		let dispatch_lost = lost.clone();
+		runtime.spawn(async move {
+			loop {
+				match message_receiver.dispatch(&scenegraph).await {
+					Ok(()) => (),
+					Err(MessengerError::IOError { .. }) => break,
+					Err(e) => eprintln!("Stardust server sent an invalid message: {e}"),
+				}
+			}
+			// marked lost before the receiver drops every pending method, so those don't look like the server's answers
+			dispatch_lost.store(true, Ordering::Release);
+			drop(message_receiver);
+			let _ = dispatch_requests.send(Request::Disconnected);
+		});
+		runtime.spawn(process_requests(
+			request_receiver,
+			requests.clone(),
+			message_sender_handle,
+			lost.clone(),
+		));
+
+		Ok(Ipc {
+			_runtime: runtime,
+			requests,
+			next_method_id: AtomicU64::new(0),
+			lost,
+			method_timeout,
+		})
+	}
+	/// Whether the connection's closed, after which every call fails with [`IpcError::Disconnected`].
+	pub fn lost(&self) -> bool {
+		self.lost.load(Ordering::Acquire)
+	}
+	/// How long methods wait for an answer unless they say otherwise.
+	pub fn method_timeout(&self) -> Duration {
+		self.method_timeout
+	}
+
+	/// Queue a signal to send, without waiting on anything.
+	pub fn signal(
+		&self,
+		node_path: &str,
+		signal_name: &str,
+		data: Vec<u8>,
+	) -> Result<(), IpcError> {
+		if self.lost() {
+			return Err(IpcError::Disconnected);
+		}
+		self.requests
+			.send(Request::Signal {
+				node_path: node_path.to_string(),
+				signal_name: signal_name.to_string(),
+				data,
+			})
+			.map_err(|_| IpcError::Disconnected)
+	}
+	/// Call a method and block until the server answers, or until `timeout` runs out if there is one.
+	///
+	/// Any number of threads can wait on methods at once, their answers are matched back up to them on the IO thread.
+	pub fn method(
+		&self,
+		node_path: &str,
+		method_name: &str,
+		data: Vec<u8>,
+		timeout: Option<Duration>,
+	) -> Result<anyhow::Result<Vec<u8>>, IpcError> {
+		if self.lost() {
+			return Err(IpcError::Disconnected);
+		}
+		let (reply, answer) = mpsc::sync_channel(1);
+		self.requests
+			.send(Request::Method {
+				id: self.next_method_id.fetch_add(1, Ordering::Relaxed),
+				node_path: node_path.to_string(),
+				method_name: method_name.to_string(),
+				data,
+				timeout,
+				reply,
+			})
+			.map_err(|_| IpcError::Disconnected)?;
+		// the reply's dropped without an answer if the IO thread goes away
+		answer.recv().map_err(|_| IpcError::Disconnected)?
+	}
+}
+
+/// The IO thread's side, sending requests in the order they were made and matching answers back up to their callers.
+async fn process_requests(
+	mut requests: UnboundedReceiver<Request>,
+	answers: UnboundedSender<Request>,
+	message_sender: MessageSenderHandle,
+	lost: Arc<AtomicBool>,
+) {
+	// callers waiting on the server, by method id
+	let mut pending: FxHashMap<u64, MethodReply> = FxHashMap::default();
+	while let Some(request) = requests.recv().await {
+		match request {
+			Request::Signal {
+				node_path,
+				signal_name,
+				data,
+			} => {
+				if message_sender
+					.signal(&node_path, &signal_name, &data)
+					.is_err()
+				{
+					break;
+				}
+			}
+			Request::Method {
+				id,
+				node_path,
+				method_name,
+				data,
+				timeout,
+				reply,
+			} => {
+				let Ok(answer) = message_sender.method(&node_path, &method_name, &data) else {
+					let _ = reply.send(Err(IpcError::Disconnected));
+					break;
+				};
+				pending.insert(id, reply);
+				let answers = answers.clone();
+				tokio::spawn(async move {
+					let answer = match timeout {
+						Some(timeout) => tokio::time::timeout(timeout, answer).await.map_err(|_| {
+							eprintln!("Stardust server took longer than {timeout:?} to answer {method_name} on {node_path}");
+							IpcError::TimedOut
+						}),
+						None => Ok(answer.await),
+					};
+					let _ = answers.send(Request::Answer { id, answer });
+				});
+			}
+			Request::Answer { id, answer } => {
+				if let Some(reply) = pending.remove(&id) {
+					let answer = if lost.load(Ordering::Acquire) {
+						Err(IpcError::Disconnected)
+					} else {
+						answer
+					};
+					let _ = reply.send(answer);
+				}
+			}
+			Request::Disconnected => break,
+		}
+	}
+	lost.store(true, Ordering::Release);
+	// anyone still waiting finds out now rather than when their timeout runs out
+	for (_, reply) in pending.drain() {
+		let _ = reply.send(Err(IpcError::Disconnected));
+	}
+}
+
+/// Block the calling thread until `future` finishes on the IO thread, or `None` if it takes longer than `timeout`.
+///
+/// The future's spawned rather than run with `block_on` so this works no matter what the app's thread is up to, even from inside its own async runtime.
+fn wait_on<F>(runtime: &Runtime, future: F, timeout: Duration) -> Option<F::Output>
+where
+	F: Future + Send + 'static,
+	F::Output: Send + 'static,
+{
+	let (result_sender, result) = mpsc::sync_channel(1);
+	runtime.spawn(async move {
+		let _ = result_sender.send(tokio::time::timeout(timeout, future).await.ok());
+	});
+	result.recv().ok().flatten()
+}
+
+/// A second socket to the server just for file descriptors, which the messenger can't pass.
+///
+/// Each message is a swapchain's id, with the fds behind its images attached in image order, so the server can match them up with the `create_swapchain` signal naming the same id.
+pub struct FdChannel(OwnedFd);
+impl FdChannel {
+	/// Connect to the server's fd socket at `path`.
+	pub fn connect(path: &str) -> io::Result<Self> {
+		let socket =
+			unsafe { libc::socket(libc::AF_UNIX, libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC, 0) };
+		if socket < 0 {
+			return Err(io::Error::last_os_error());
+		}
+		let socket = unsafe { OwnedFd::from_raw_fd(socket) };
+
+		let mut address: libc::sockaddr_un = unsafe { zeroed() };
+		address.sun_family = libc::AF_UNIX as libc::sa_family_t;
+		// room for the nul on the end
+		if path.len() >= address.sun_path.len() {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				"socket path too long",
+			));
+		}
+		for (c, byte) in address.sun_path.iter_mut().zip(path.bytes()) {
+			*c = byte as libc::c_char;
+		}
+		let connected = unsafe {
+			libc::connect(
+				socket.as_raw_fd(),
+				&address as *const libc::sockaddr_un as *const libc::sockaddr,
+				size_of::<libc::sockaddr_un>() as libc::socklen_t,
+			)
+		};
+		if connected < 0 {
+			return Err(io::Error::last_os_error());
+		}
+		Ok(FdChannel(socket))
+	}
+	/// Send `fds` along with the `id` of the swapchain they belong to.
+	///
+	/// The server gets its own copies, so ours can be closed whenever.
+	pub fn send(&self, id: &str, fds: &[BorrowedFd]) -> io::Result<()> {
+		let fds: Vec<RawFd> = fds.iter().map(AsRawFd::as_raw_fd).collect();
+		let fds_size = size_of_val(fds.as_slice()) as u32;
+		// u64s so the control message header's aligned
+		let control_len = unsafe { libc::CMSG_SPACE(fds_size) } as usize;
+		let mut control = vec![0_u64; control_len.div_ceil(size_of::<u64>())];
+		let mut data = libc::iovec {
+			iov_base: id.as_ptr() as *mut c_void,
+			iov_len: id.len(),
+		};
+		let mut message: libc::msghdr = unsafe { zeroed() };
+		message.msg_iov = &mut data;
+		message.msg_iovlen = 1;
+		message.msg_control = control.as_mut_ptr().cast();
+		message.msg_controllen = control_len as _;
+		unsafe {
+			let header = libc::CMSG_FIRSTHDR(&message);
+			(*header).cmsg_level = libc::SOL_SOCKET;
+			(*header).cmsg_type = libc::SCM_RIGHTS;
+			(*header).cmsg_len = libc::CMSG_LEN(fds_size) as _;
+			ptr::copy_nonoverlapping(fds.as_ptr(), libc::CMSG_DATA(header).cast(), fds.len());
+		}
+		// a server that's gone shouldn't take the app down with SIGPIPE
+		if unsafe { libc::sendmsg(self.0.as_raw_fd(), &message, libc::MSG_NOSIGNAL) } < 0 {
+			return Err(io::Error::last_os_error());
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::{
+		fs::File,
+		io::{Read, Seek, Write},
+		os::fd::AsFd,
+	};
+
+	/// Receive one message like the server would, returning its id and fds.
+	fn receive(socket: &OwnedFd) -> (String, Vec<OwnedFd>) {
+		let mut id = [0_u8; 64];
+		let mut control = [0_u64; 16];
+		let mut data = libc::iovec {
+			iov_base: id.as_mut_ptr().cast(),
+			iov_len: id.len(),
+		};
+		let mut message: libc::msghdr = unsafe { zeroed() };
+		message.msg_iov = &mut data;
+		message.msg_iovlen = 1;
+		message.msg_control = control.as_mut_ptr().cast();
+		message.msg_controllen = size_of_val(&control) as _;
+		let id_len = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut message, 0) };
+		assert!(id_len >= 0, "{}", io::Error::last_os_error());
+
+		let header = unsafe { &*libc::CMSG_FIRSTHDR(&message) };
+		assert_eq!(header.cmsg_type, libc::SCM_RIGHTS);
+		let fd_count =
+			(header.cmsg_len as usize - unsafe { libc::CMSG_LEN(0) } as usize) / size_of::<RawFd>();
+		let fds = (0..fd_count)
+			.map(|i| unsafe {
+				let fd = libc::CMSG_DATA(header)
+					.cast::<RawFd>()
+					.add(i)
+					.read_unaligned();
+				OwnedFd::from_raw_fd(fd)
+			})
+			.collect();
+		let id = String::from_utf8(id[..id_len as usize].to_vec()).unwrap();
+		(id, fds)
+	}
+
+	#[test]
+	fn fds_arrive_with_their_swapchain_id() {
+		let mut sockets = [0; 2];
+		let paired = unsafe {
+			libc::socketpair(
+				libc::AF_UNIX,
+				libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC,
+				0,
+				sockets.as_mut_ptr(),
+			)
+		};
+		assert_eq!(paired, 0);
+		let [ours, servers] = sockets.map(|fd| unsafe { OwnedFd::from_raw_fd(fd) });
+		let channel = FdChannel(ours);
+
+		let mut files: Vec<File> = (0..2).map(|_| tempfile()).collect();
+		for (i, file) in files.iter_mut().enumerate() {
+			write!(file, "image {i}").unwrap();
+		}
+		let fds: Vec<BorrowedFd> = files.iter().map(AsFd::as_fd).collect();
+		channel.send("swapchain", &fds).unwrap();
+		drop(files);
+
+		let (id, fds) = receive(&servers);
+		assert_eq!(id, "swapchain");
+		assert_eq!(fds.len(), 2);
+		// still readable once our copies are closed, and in the order they were sent
+		for (i, fd) in fds.into_iter().enumerate() {
+			let mut file = File::from(fd);
+			file.rewind().unwrap();
+			let mut contents = String::new();
+			file.read_to_string(&mut contents).unwrap();
+			assert_eq!(contents, format!("image {i}"));
+		}
+	}
+
+	fn tempfile() -> File {
+		let fd = unsafe { libc::memfd_create(c"fd-channel-test".as_ptr(), libc::MFD_CLOEXEC) };
+		assert!(fd >= 0);
+		File::from(unsafe { OwnedFd::from_raw_fd(fd) })
+	}
+}
@@ -0,0 +1,325 @@
+use crate::{
+	handle::Handle,
+	instance::StardustInstance,
+	space::pose_valid,
+	util::{find_in_next_chain, StardustFov, StardustPose},
+	XrResult,
+};
+use mint::{Quaternion, Vector2};
+use openxr_sys::{
+	CompositionLayerBaseHeader, CompositionLayerCubeKHR, CompositionLayerCylinderKHR,
+	CompositionLayerDepthInfoKHR, CompositionLayerEquirect2KHR, CompositionLayerProjection,
+	CompositionLayerProjectionView, CompositionLayerQuad, FrameEndInfo, StructureType,
+	SwapchainSubImage,
+};
+use serde::Serialize;
+use std::slice;
+
+#[derive(Debug, Serialize)]
+pub struct StardustRect {
+	x: i32,
+	y: i32,
+	width: i32,
+	height: i32,
+}
+
+/// The part of a swapchain image a layer shows, with the image being whichever the app released last.
+#[derive(Debug, Serialize)]
+pub struct StardustSubImage {
+	swapchain: String,
+	image_index: u32,
+	rect: StardustRect,
+	array_index: u32,
+}
+impl StardustSubImage {
+	fn new(sub_image: &SwapchainSubImage) -> Result<Self, XrResult> {
+		let swapchain = sub_image.swapchain.get_stardust()?;
+		let rect = sub_image.image_rect;
+		let (x, y) = (rect.offset.x as i64, rect.offset.y as i64);
+		let (width, height) = (rect.extent.width as i64, rect.extent.height as i64);
+		if x < 0
+			|| y < 0 || width <= 0
+			|| height <= 0
+			|| x + width > swapchain.width() as i64
+			|| y + height > swapchain.height() as i64
+		{
+			return Err(XrResult::ERROR_SWAPCHAIN_RECT_INVALID);
+		}
+		if sub_image.image_array_index >= swapchain.array_size() {
+			return Err(XrResult::ERROR_VALIDATION_FAILURE);
+		}
+		// there's nothing to show until the app has released an image
+		let image_index = swapchain
+			.released_image()
+			.ok_or(XrResult::ERROR_LAYER_INVALID)?;
+
+		Ok(StardustSubImage {
+			swapchain: swapchain.node_path().to_string(),
+			image_index,
+			rect: StardustRect {
+				x: rect.offset.x,
+				y: rect.offset.y,
+				width: rect.extent.width,
+				height: rect.extent.height,
+			},
+			array_index: sub_image.image_array_index,
+		})
+	}
+}
+
+/// XR_KHR_composition_layer_depth, the depth buffer behind a projection view so the server can occlude it properly.
+#[derive(Debug, Serialize)]
+pub struct StardustDepthInfo {
+	sub_image: StardustSubImage,
+	/// The range of depth values the app wrote, within 0..=1
+	min_depth: f32,
+	max_depth: f32,
+	/// Distances in meters that `min_depth` and `max_depth` map to, `far_z` being infinite for infinite projections
+	near_z: f32,
+	far_z: f32,
+}
+impl StardustDepthInfo {
+	fn new(depth_info: &CompositionLayerDepthInfoKHR) -> Result<Self, XrResult> {
+		let (min_depth, max_depth) = (depth_info.min_depth, depth_info.max_depth);
+		if !(0.0..=1.0).contains(&min_depth)
+			|| !(0.0..=1.0).contains(&max_depth)
+			|| min_depth > max_depth
+			|| depth_info.near_z == depth_info.far_z
+		{
+			return Err(XrResult::ERROR_VALIDATION_FAILURE);
+		}
+		Ok(StardustDepthInfo {
+			sub_image: StardustSubImage::new(&depth_info.sub_image)?,
+			min_depth,
+			max_depth,
+			near_z: depth_info.near_z,
+			far_z: depth_info.far_z,
+		})
+	}
+}
+
+#[derive(Debug, Serialize)]
+pub struct StardustProjectionView {
+	pose: StardustPose,
+	fov: StardustFov,
+	sub_image: StardustSubImage,
+	depth: Option<StardustDepthInfo>,
+}
+impl StardustProjectionView {
+	/// # Safety
+	/// `view.next` must be a valid structure chain
+	unsafe fn new(
+		instance: &StardustInstance,
+		view: &CompositionLayerProjectionView,
+	) -> Result<Self, XrResult> {
+		if !pose_valid(&view.pose) {
+			return Err(XrResult::ERROR_POSE_INVALID);
+		}
+		let depth = if instance.extension_composition_layer_depth_enabled {
+			find_in_next_chain::<CompositionLayerDepthInfoKHR>(
+				view.next,
+				StructureType::COMPOSITION_LAYER_DEPTH_INFO_KHR,
+			)
+			.map(StardustDepthInfo::new)
+			.transpose()?
+		} else {
+			None
+		};
+		Ok(StardustProjectionView {
+			pose: view.pose.into(),
+			fov: view.fov.into(),
+			sub_image: StardustSubImage::new(&view.sub_image)?,
+			depth,
+		})
+	}
+}
+
+/// A composition layer as the server composites it, relative to the space node at `space`.
+#[derive(Debug, Serialize)]
+pub enum StardustLayer {
+	Projection {
+		flags: u64,
+		space: String,
+		views: Vec<StardustProjectionView>,
+	},
+	/// A textured panel placed among the other clients in the scene
+	Quad {
+		flags: u64,
+		space: String,
+		eye_visibility: i32,
+		pose: StardustPose,
+		/// Width and height in meters
+		size: Vector2<f32>,
+		sub_image: StardustSubImage,
+	},
+	/// XR_KHR_composition_layer_cylinder, a curved panel
+	Cylinder {
+		flags: u64,
+		space: String,
+		eye_visibility: i32,
+		pose: StardustPose,
+		radius: f32,
+		central_angle: f32,
+		aspect_ratio: f32,
+		sub_image: StardustSubImage,
+	},
+	/// XR_KHR_composition_layer_equirect2, a sphere section usually showing 360 video
+	Equirect {
+		flags: u64,
+		space: String,
+		eye_visibility: i32,
+		pose: StardustPose,
+		/// 0 for an infinitely far away sphere
+		radius: f32,
+		central_horizontal_angle: f32,
+		upper_vertical_angle: f32,
+		lower_vertical_angle: f32,
+		sub_image: StardustSubImage,
+	},
+	/// XR_KHR_composition_layer_cube, a skybox from a cubemap swapchain
+	Cube {
+		flags: u64,
+		space: String,
+		eye_visibility: i32,
+		orientation: Quaternion<f32>,
+		swapchain: String,
+		image_index: u32,
+		array_index: u32,
+	},
+}
+impl StardustLayer {
+	/// # Safety
+	/// `header` must be the start of the layer struct its `ty` says it is
+	unsafe fn new(
+		instance: &StardustInstance,
+		header: &CompositionLayerBaseHeader,
+		view_count: usize,
+	) -> Result<Self, XrResult> {
+		let flags = header.layer_flags.into_raw();
+		let space = header.space.get_stardust()?.node_path().to_string();
+		match header.ty {
+			StructureType::COMPOSITION_LAYER_PROJECTION => {
+				let layer = &*(header as *const _ as *const CompositionLayerProjection);
+				if layer.view_count as usize != view_count || layer.views.is_null() {
+					return Err(XrResult::ERROR_VALIDATION_FAILURE);
+				}
+				let views = slice::from_raw_parts(layer.views, view_count)
+					.iter()
+					.map(|view| StardustProjectionView::new(instance, view))
+					.collect::<Result<_, _>>()?;
+				Ok(StardustLayer::Projection {
+					flags,
+					space,
+					views,
+				})
+			}
+			StructureType::COMPOSITION_LAYER_QUAD => {
+				let layer = &*(header as *const _ as *const CompositionLayerQuad);
+				if !pose_valid(&layer.pose) {
+					return Err(XrResult::ERROR_POSE_INVALID);
+				}
+				Ok(StardustLayer::Quad {
+					flags,
+					space,
+					eye_visibility: layer.eye_visibility.into_raw(),
+					pose: layer.pose.into(),
+					size: Vector2 {
+						x: layer.size.width,
+						y: layer.size.height,
+					},
+					sub_image: StardustSubImage::new(&layer.sub_image)?,
+				})
+			}
+			StructureType::COMPOSITION_LAYER_CYLINDER_KHR
+				if instance.extension_composition_layer_cylinder_enabled =>
+			{
+				let layer = &*(header as *const _ as *const CompositionLayerCylinderKHR);
+				if !pose_valid(&layer.pose) {
+					return Err(XrResult::ERROR_POSE_INVALID);
+				}
+				Ok(StardustLayer::Cylinder {
+					flags,
+					space,
+					eye_visibility: layer.eye_visibility.into_raw(),
+					pose: layer.pose.into(),
+					radius: layer.radius,
+					central_angle: layer.central_angle,
+					aspect_ratio: layer.aspect_ratio,
+					sub_image: StardustSubImage::new(&layer.sub_image)?,
+				})
+			}
+			StructureType::COMPOSITION_LAYER_EQUIRECT2_KHR
+				if instance.extension_composition_layer_equirect2_enabled =>
+			{
+				let layer = &*(header as *const _ as *const CompositionLayerEquirect2KHR);
+				if !pose_valid(&layer.pose) {
+					return Err(XrResult::ERROR_POSE_INVALID);
+				}
+				Ok(StardustLayer::Equirect {
+					flags,
+					space,
+					eye_visibility: layer.eye_visibility.into_raw(),
+					pose: layer.pose.into(),
+					radius: layer.radius,
+					central_horizontal_angle: layer.central_horizontal_angle,
+					upper_vertical_angle: layer.upper_vertical_angle,
+					lower_vertical_angle: layer.lower_vertical_angle,
+					sub_image: StardustSubImage::new(&layer.sub_image)?,
+				})
+			}
+			StructureType::COMPOSITION_LAYER_CUBE_KHR
+				if instance.extension_composition_layer_cube_enabled =>
+			{
+				let layer = &*(header as *const _ as *const CompositionLayerCubeKHR);
+				let swapchain = layer.swapchain.get_stardust()?;
+				if swapchain.face_count() != 6 || layer.image_array_index >= swapchain.array_size()
+				{
+					return Err(XrResult::ERROR_VALIDATION_FAILURE);
+				}
+				Ok(StardustLayer::Cube {
+					flags,
+					space,
+					eye_visibility: layer.eye_visibility.into_raw(),
+					orientation: layer.orientation.into(),
+					swapchain: swapchain.node_path().to_string(),
+					image_index: swapchain
+						.released_image()
+						.ok_or(XrResult::ERROR_LAYER_INVALID)?,
+					array_index: layer.image_array_index,
+				})
+			}
+			_ => Err(XrResult::ERROR_LAYER_INVALID),
+		}
+	}
+}
+
+/// Validate and convert every layer the app submitted in xrEndFrame.
+///
+/// # Safety
+/// `frame_end_info.layers` must point to `layer_count` layer pointers
+pub unsafe fn layers_from_frame_end_info(
+	instance: &StardustInstance,
+	frame_end_info: &FrameEndInfo,
+	view_count: usize,
+	max_layer_count: u32,
+) -> Result<Vec<StardustLayer>, XrResult> {
+	if frame_end_info.layer_count > max_layer_count {
+		return Err(XrResult::ERROR_LAYER_LIMIT_EXCEEDED);
+	}
+	if frame_end_info.layer_count == 0 {
+		return Ok(Vec::new());
+	}
+	if frame_end_info.layers.is_null() {
+		return Err(XrResult::ERROR_LAYER_INVALID);
+	}
+	slice::from_raw_parts(frame_end_info.layers, frame_end_info.layer_count as usize)
+		.iter()
+		.map(|layer| {
+			StardustLayer::new(
+				instance,
+				layer.as_ref().ok_or(XrResult::ERROR_LAYER_INVALID)?,
+				view_count,
+			)
+		})
+		.collect()
+}
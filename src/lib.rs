@@ -1,16 +1,31 @@
 #[macro_use]
 pub mod util;
+pub mod events;
 pub mod extensions;
+pub mod frame;
+pub mod graphics;
+pub mod handle;
 pub mod input;
 pub mod instance;
+pub mod ipc;
+pub mod layer;
+pub mod opengl;
+pub mod pose_cache;
 pub mod session;
+pub mod shm;
+pub mod space;
 mod string;
+#[cfg(test)]
+mod stub_server;
+pub mod swapchain;
 pub mod system;
+pub mod vulkan;
 pub mod wip;
 
 pub use openxr_sys as oxr;
 
 use extensions::xrEnumerateInstanceExtensionProperties;
+use handle::Handle;
 use instance::xrCreateInstance;
 use oxr::{
 	loader::{XrNegotiateLoaderInfo, XrNegotiateRuntimeRequest, CURRENT_LOADER_RUNTIME_VERSION},
@@ -21,7 +36,7 @@ use std::{
 	ffi::c_char,
 	mem::{size_of, transmute},
 };
-use util::{enumerate, str_from_const_char, Handle};
+use util::{enumerate, str_from_const_char};
 
 pub type XrResult = openxr_sys::Result;
 
@@ -91,7 +106,8 @@ fn get_instance_proc_addr(
 			xrEnumerateApiLayerProperties,
 			xrCreateInstance
 		],
-		Some(instance) => instance.get_stardust()?.get_proc_addr(name),
+		// a lost instance still has to be destroyed
+		Some(instance) => instance.get_stardust_even_if_lost()?.get_proc_addr(name),
 	}
 }
 
@@ -0,0 +1,727 @@
+use crate::{
+	handle::Handle,
+	shm::MemorySwapchainImages,
+	swapchain::{DmabufImage, SwapchainBuffers, DRM_FORMAT_ABGR8888},
+	XrResult,
+};
+use openxr_sys::{
+	platform::{EGLContext, EGLDisplay},
+	GraphicsBindingEGLMNDX, GraphicsRequirementsOpenGLKHR, Instance, SwapchainCreateInfo,
+	SwapchainImageOpenGLKHR, SystemId, Version,
+};
+use std::{
+	ffi::{c_char, c_void, CStr},
+	mem::transmute_copy,
+	os::fd::{AsFd, BorrowedFd, FromRawFd, OwnedFd},
+	ptr,
+	sync::{atomic::Ordering, OnceLock},
+};
+
+/// Swapchain textures use immutable storage, which is core from 4.2.
+const MIN_OPENGL_VERSION: Version = Version::new(4, 2, 0);
+const MAX_OPENGL_VERSION: Version = Version::new(4, 6, 0);
+
+const GL_TEXTURE_2D: u32 = 0x0DE1;
+const GL_TEXTURE_BINDING_2D: u32 = 0x8069;
+const GL_RGBA8: u32 = 0x8058;
+const GL_SRGB8_ALPHA8: u32 = 0x8C43;
+const GL_RGBA: u32 = 0x1908;
+const GL_UNSIGNED_BYTE: u32 = 0x1401;
+const GL_PACK_ROW_LENGTH: u32 = 0x0D02;
+const GL_PACK_SKIP_ROWS: u32 = 0x0D03;
+const GL_PACK_SKIP_PIXELS: u32 = 0x0D04;
+const GL_PACK_ALIGNMENT: u32 = 0x0D05;
+const GL_PIXEL_PACK_BUFFER: u32 = 0x88EB;
+const GL_PIXEL_PACK_BUFFER_BINDING: u32 = 0x88ED;
+const EGL_NONE: i32 = 0x3038;
+const EGL_EXTENSIONS: i32 = 0x3055;
+const EGL_GL_TEXTURE_2D_KHR: u32 = 0x30B1;
+const EGL_GL_TEXTURE_LEVEL_KHR: i32 = 0x30BC;
+
+/// Swapchain formats in order of preference.
+const SWAPCHAIN_FORMATS: [u32; 2] = [GL_SRGB8_ALPHA8, GL_RGBA8];
+/// Depth formats apps might ask for, which neither EGL images nor shared memory can get to the server, so depth layers need Vulkan.
+const DEPTH_FORMATS: [u32; 4] = [
+	0x81A5, // GL_DEPTH_COMPONENT16
+	0x81A6, // GL_DEPTH_COMPONENT24
+	0x8CAC, // GL_DEPTH_COMPONENT32F
+	0x88F0, // GL_DEPTH24_STENCIL8
+];
+pub fn swapchain_formats() -> Vec<i64> {
+	SWAPCHAIN_FORMATS.iter().map(|f| *f as i64).collect()
+}
+
+type GetProcAddress =
+	unsafe extern "system" fn(*const c_char) -> Option<unsafe extern "system" fn()>;
+type EGLImage = *mut c_void;
+
+/// `eglGetProcAddress` from the system's libEGL, for apps that hand us a GLX or Wayland binding instead of their own.
+fn egl_get_proc_address() -> Option<GetProcAddress> {
+	static LIBEGL: OnceLock<Option<libloading::Library>> = OnceLock::new();
+	let libegl = LIBEGL
+		.get_or_init(|| unsafe { libloading::Library::new("libEGL.so.1") }.ok())
+		.as_ref()?;
+	let get_proc_address = unsafe { libegl.get::<GetProcAddress>(b"eglGetProcAddress\0") }.ok()?;
+	Some(*get_proc_address)
+}
+
+/// The app's GL context from one of the `XrGraphicsBindingOpenGL*KHR` or `XrGraphicsBindingEGLMNDX`.
+///
+/// Swapchain textures are exported through EGL, so only XR_MNDX_egl_enable tells us everything up front.
+/// For the others we use whichever EGL context is current when swapchains are created, which the spec requires to be the app's.
+/// GLX contexts have no EGL context to export from, so their textures are copied into shared memory instead.
+#[derive(Debug, Clone, Copy)]
+pub struct OpenGLBinding {
+	get_proc_address: GetProcAddress,
+	egl: Option<(EGLDisplay, EGLContext)>,
+}
+// EGL handles are valid on any thread, only making a context current is tied to one
+unsafe impl Send for OpenGLBinding {}
+unsafe impl Sync for OpenGLBinding {}
+impl OpenGLBinding {
+	/// For the Xlib, XCB and Wayland bindings, none of which we need anything from.
+	pub fn new() -> Result<Self, XrResult> {
+		Ok(OpenGLBinding {
+			get_proc_address: egl_get_proc_address()
+				.ok_or(XrResult::ERROR_GRAPHICS_DEVICE_INVALID)?,
+			egl: None,
+		})
+	}
+	pub fn new_egl(binding: &GraphicsBindingEGLMNDX) -> Result<Self, XrResult> {
+		if binding.display.is_null() || binding.context.is_null() {
+			return Err(XrResult::ERROR_GRAPHICS_DEVICE_INVALID);
+		}
+		Ok(OpenGLBinding {
+			get_proc_address: binding.get_proc_address,
+			egl: Some((binding.display, binding.context)),
+		})
+	}
+}
+
+unsafe fn load<F>(get_proc_address: GetProcAddress, name: &[u8]) -> Result<F, XrResult> {
+	let function = get_proc_address(name.as_ptr() as *const c_char)
+		.ok_or(XrResult::ERROR_GRAPHICS_DEVICE_INVALID)?;
+	Ok(transmute_copy::<unsafe extern "system" fn(), F>(&function))
+}
+
+/// The EGL and GL entry points every swapchain needs, loaded through the binding's `get_proc_address`.
+struct GlFns {
+	get_current_display: unsafe extern "system" fn() -> EGLDisplay,
+	get_current_context: unsafe extern "system" fn() -> EGLContext,
+	query_string: unsafe extern "system" fn(EGLDisplay, i32) -> *const c_char,
+	gen_textures: unsafe extern "system" fn(i32, *mut u32),
+	delete_textures: unsafe extern "system" fn(i32, *const u32),
+	bind_texture: unsafe extern "system" fn(u32, u32),
+	tex_storage_2d: unsafe extern "system" fn(u32, i32, u32, i32, i32),
+	get_integerv: unsafe extern "system" fn(u32, *mut i32),
+	get_tex_image: unsafe extern "system" fn(u32, i32, u32, u32, *mut c_void),
+	pixel_storei: unsafe extern "system" fn(u32, i32),
+	bind_buffer: unsafe extern "system" fn(u32, u32),
+}
+impl GlFns {
+	unsafe fn load(get_proc_address: GetProcAddress) -> Result<Self, XrResult> {
+		Ok(GlFns {
+			get_current_display: load(get_proc_address, b"eglGetCurrentDisplay\0")?,
+			get_current_context: load(get_proc_address, b"eglGetCurrentContext\0")?,
+			query_string: load(get_proc_address, b"eglQueryString\0")?,
+			gen_textures: load(get_proc_address, b"glGenTextures\0")?,
+			delete_textures: load(get_proc_address, b"glDeleteTextures\0")?,
+			bind_texture: load(get_proc_address, b"glBindTexture\0")?,
+			tex_storage_2d: load(get_proc_address, b"glTexStorage2D\0")?,
+			get_integerv: load(get_proc_address, b"glGetIntegerv\0")?,
+			get_tex_image: load(get_proc_address, b"glGetTexImage\0")?,
+			pixel_storei: load(get_proc_address, b"glPixelStorei\0")?,
+			bind_buffer: load(get_proc_address, b"glBindBuffer\0")?,
+		})
+	}
+	/// Whether `display` can export textures as dmabufs, which Mesa's software drivers and GLX contexts can't.
+	unsafe fn can_export_dmabufs(&self, display: EGLDisplay) -> bool {
+		if display.is_null() {
+			return false;
+		}
+		let extensions = (self.query_string)(display, EGL_EXTENSIONS);
+		!extensions.is_null()
+			&& CStr::from_ptr(extensions)
+				.to_bytes()
+				.split(|c| *c == b' ')
+				.any(|extension| extension == b"EGL_MESA_image_dma_buf_export")
+	}
+	/// Make the texture bound to `GL_TEXTURE_2D` `texture` while `f` runs, putting back whatever the app had bound.
+	unsafe fn with_texture<R>(&self, texture: u32, f: impl FnOnce() -> R) -> R {
+		let mut previous_texture = 0;
+		(self.get_integerv)(GL_TEXTURE_BINDING_2D, &mut previous_texture);
+		(self.bind_texture)(GL_TEXTURE_2D, texture);
+		let result = f();
+		(self.bind_texture)(GL_TEXTURE_2D, previous_texture as u32);
+		result
+	}
+	fn new_texture(&self, create_info: &SwapchainCreateInfo, format: u32) -> u32 {
+		let mut texture = 0;
+		unsafe {
+			(self.gen_textures)(1, &mut texture);
+			self.with_texture(texture, || {
+				(self.tex_storage_2d)(
+					GL_TEXTURE_2D,
+					create_info.mip_count as i32,
+					format,
+					create_info.width as i32,
+					create_info.height as i32,
+				)
+			});
+		}
+		texture
+	}
+}
+
+/// The EGL entry points for exporting textures as dmabufs, only loaded once we know the display supports them.
+struct EglImageFns {
+	create_image:
+		unsafe extern "system" fn(EGLDisplay, EGLContext, u32, *mut c_void, *const i32) -> EGLImage,
+	destroy_image: unsafe extern "system" fn(EGLDisplay, EGLImage) -> u32,
+	export_dmabuf_image_query:
+		unsafe extern "system" fn(EGLDisplay, EGLImage, *mut i32, *mut i32, *mut u64) -> u32,
+	export_dmabuf_image:
+		unsafe extern "system" fn(EGLDisplay, EGLImage, *mut i32, *mut i32, *mut i32) -> u32,
+}
+impl EglImageFns {
+	unsafe fn load(get_proc_address: GetProcAddress) -> Result<Self, XrResult> {
+		Ok(EglImageFns {
+			create_image: load(get_proc_address, b"eglCreateImageKHR\0")?,
+			destroy_image: load(get_proc_address, b"eglDestroyImageKHR\0")?,
+			export_dmabuf_image_query: load(get_proc_address, b"eglExportDMABUFImageQueryMESA\0")?,
+			export_dmabuf_image: load(get_proc_address, b"eglExportDMABUFImageMESA\0")?,
+		})
+	}
+}
+
+/// How the server gets at a swapchain's textures.
+enum TextureSharing {
+	/// Each texture's backed by an EGL image exported as a dmabuf, so the server reads straight from the GPU
+	Dmabuf {
+		fns: EglImageFns,
+		display: EGLDisplay,
+		egl_images: Vec<EGLImage>,
+		dmabufs: Vec<OwnedFd>,
+	},
+	/// Nothing to export the textures with, as with GLX contexts, so each is read back into shared memory when it's released
+	Copy(MemorySwapchainImages),
+}
+
+/// Swapchain textures in the app's GL context, shared with the server however its context allows.
+pub struct OpenGLSwapchainImages {
+	fns: GlFns,
+	textures: Vec<u32>,
+	width: u32,
+	height: u32,
+	sharing: TextureSharing,
+}
+// the EGL images are only ever touched again to destroy them, which EGL allows from any thread,
+// and the copies only happen in swapchain calls, which the app makes with its context current
+unsafe impl Send for OpenGLSwapchainImages {}
+unsafe impl Sync for OpenGLSwapchainImages {}
+impl OpenGLSwapchainImages {
+	/// # Safety
+	/// The app's GL context has to be current on this thread.
+	pub unsafe fn new(
+		binding: &OpenGLBinding,
+		create_info: &SwapchainCreateInfo,
+		image_count: u32,
+	) -> Result<(Self, SwapchainBuffers), XrResult> {
+		if DEPTH_FORMATS
+			.iter()
+			.any(|f| *f as i64 == create_info.format)
+		{
+			eprintln!("Depth swapchains need Vulkan, OpenGL depth textures can't be shared with the Stardust server");
+			return Err(XrResult::ERROR_SWAPCHAIN_FORMAT_UNSUPPORTED);
+		}
+		let format = SWAPCHAIN_FORMATS
+			.into_iter()
+			.find(|f| *f as i64 == create_info.format)
+			.ok_or(XrResult::ERROR_SWAPCHAIN_FORMAT_UNSUPPORTED)?;
+		if create_info.sample_count != 1
+			|| create_info.array_size != 1
+			|| create_info.face_count != 1
+		{
+			// EGL images can only be made from a single plain 2D texture, and the copies are done the same way
+			return Err(XrResult::ERROR_FEATURE_UNSUPPORTED);
+		}
+
+		let fns = GlFns::load(binding.get_proc_address)?;
+		let (display, context) = match binding.egl {
+			Some(egl) => egl,
+			// null under GLX
+			None => ((fns.get_current_display)(), (fns.get_current_context)()),
+		};
+		if !context.is_null() && fns.can_export_dmabufs(display) {
+			let egl_fns = EglImageFns::load(binding.get_proc_address)?;
+			Self::new_dmabuf(
+				fns,
+				egl_fns,
+				display,
+				context,
+				create_info,
+				format,
+				image_count,
+			)
+		} else {
+			Self::new_copied(fns, create_info, format, image_count)
+		}
+	}
+	unsafe fn new_dmabuf(
+		fns: GlFns,
+		egl_fns: EglImageFns,
+		display: EGLDisplay,
+		context: EGLContext,
+		create_info: &SwapchainCreateInfo,
+		format: u32,
+		image_count: u32,
+	) -> Result<(Self, SwapchainBuffers), XrResult> {
+		// anything that fails partway through is cleaned up by dropping this
+		let mut swapchain_images = OpenGLSwapchainImages {
+			fns,
+			textures: Vec::new(),
+			width: create_info.width,
+			height: create_info.height,
+			sharing: TextureSharing::Dmabuf {
+				fns: egl_fns,
+				display,
+				egl_images: Vec::new(),
+				dmabufs: Vec::new(),
+			},
+		};
+		let mut fourcc = 0;
+		let mut dmabuf_images = Vec::new();
+		for _ in 0..image_count {
+			let texture = swapchain_images.fns.new_texture(create_info, format);
+			swapchain_images.textures.push(texture);
+			let TextureSharing::Dmabuf {
+				fns,
+				egl_images,
+				dmabufs,
+				..
+			} = &mut swapchain_images.sharing
+			else {
+				unreachable!()
+			};
+
+			let attributes = [EGL_GL_TEXTURE_LEVEL_KHR, 0, EGL_NONE];
+			let egl_image = (fns.create_image)(
+				display,
+				context,
+				EGL_GL_TEXTURE_2D_KHR,
+				texture as usize as *mut c_void,
+				attributes.as_ptr(),
+			);
+			if egl_image.is_null() {
+				return Err(XrResult::ERROR_RUNTIME_FAILURE);
+			}
+			egl_images.push(egl_image);
+
+			// the driver picks the layout, so unlike Vulkan there's no negotiating the modifier with the server
+			let mut plane_count = 0;
+			let mut modifier = 0;
+			let (mut fd, mut stride, mut offset) = (-1, 0, 0);
+			if (fns.export_dmabuf_image_query)(
+				display,
+				egl_image,
+				&mut fourcc,
+				&mut plane_count,
+				&mut modifier,
+			) == 0 || plane_count != 1
+				|| (fns.export_dmabuf_image)(display, egl_image, &mut fd, &mut stride, &mut offset)
+					== 0
+			{
+				return Err(XrResult::ERROR_RUNTIME_FAILURE);
+			}
+			let fd = OwnedFd::from_raw_fd(fd);
+			dmabuf_images.push(DmabufImage {
+				modifier,
+				offset: offset as u64,
+				stride: stride as u64,
+				array_pitch: 0,
+			});
+			dmabufs.push(fd);
+		}
+
+		Ok((
+			swapchain_images,
+			SwapchainBuffers::Dmabuf {
+				fourcc: fourcc as u32,
+				srgb: format == GL_SRGB8_ALPHA8,
+				flip_y: true,
+				images: dmabuf_images,
+			},
+		))
+	}
+	unsafe fn new_copied(
+		fns: GlFns,
+		create_info: &SwapchainCreateInfo,
+		format: u32,
+		image_count: u32,
+	) -> Result<(Self, SwapchainBuffers), XrResult> {
+		// read back as bytes in R, G, B, A order, and the server treats shared memory as sRGB whatever the texture was
+		let (memory_images, buffers) = MemorySwapchainImages::new(
+			&SwapchainCreateInfo {
+				format: DRM_FORMAT_ABGR8888 as i64,
+				..*create_info
+			},
+			image_count,
+		)?;
+		let textures = (0..image_count)
+			.map(|_| fns.new_texture(create_info, format))
+			.collect();
+		Ok((
+			OpenGLSwapchainImages {
+				fns,
+				textures,
+				width: create_info.width,
+				height: create_info.height,
+				sharing: TextureSharing::Copy(memory_images),
+			},
+			buffers,
+		))
+	}
+
+	/// The app's done rendering to image `index`, so get it to wherever the server reads it from.
+	///
+	/// # Safety
+	/// The app's GL context has to be current on this thread, which the spec has it be for any swapchain call.
+	pub unsafe fn released(&self, index: u32) {
+		let TextureSharing::Copy(memory_images) = &self.sharing else {
+			return;
+		};
+		let fns = &self.fns;
+		let row_pitch = memory_images.row_pitch() as usize;
+		let data = memory_images.data(index as usize);
+
+		// the app's pixel pack state would otherwise scatter the pixels anywhere, including into a buffer of its own
+		let pack_state = [
+			GL_PACK_ROW_LENGTH,
+			GL_PACK_SKIP_ROWS,
+			GL_PACK_SKIP_PIXELS,
+			GL_PACK_ALIGNMENT,
+			GL_PIXEL_PACK_BUFFER_BINDING,
+		]
+		.map(|parameter| {
+			let mut value = 0;
+			(fns.get_integerv)(parameter, &mut value);
+			value
+		});
+		(fns.bind_buffer)(GL_PIXEL_PACK_BUFFER, 0);
+		(fns.pixel_storei)(GL_PACK_ROW_LENGTH, (row_pitch / 4) as i32);
+		(fns.pixel_storei)(GL_PACK_SKIP_ROWS, 0);
+		(fns.pixel_storei)(GL_PACK_SKIP_PIXELS, 0);
+		(fns.pixel_storei)(GL_PACK_ALIGNMENT, 4);
+		fns.with_texture(self.textures[index as usize], || {
+			(fns.get_tex_image)(
+				GL_TEXTURE_2D,
+				0,
+				GL_RGBA,
+				GL_UNSIGNED_BYTE,
+				data as *mut c_void,
+			)
+		});
+		(fns.pixel_storei)(GL_PACK_ROW_LENGTH, pack_state[0]);
+		(fns.pixel_storei)(GL_PACK_SKIP_ROWS, pack_state[1]);
+		(fns.pixel_storei)(GL_PACK_SKIP_PIXELS, pack_state[2]);
+		(fns.pixel_storei)(GL_PACK_ALIGNMENT, pack_state[3]);
+		(fns.bind_buffer)(GL_PIXEL_PACK_BUFFER, pack_state[4] as u32);
+
+		// GL puts the first row at the bottom, and there's no telling the server to flip shared memory
+		let height = self.height as usize;
+		let row_size = self.width as usize * 4;
+		for row in 0..height / 2 {
+			ptr::swap_nonoverlapping(
+				data.add(row * row_pitch),
+				data.add((height - 1 - row) * row_pitch),
+				row_size,
+			);
+		}
+	}
+
+	pub fn xr_images(&self) -> Vec<SwapchainImageOpenGLKHR> {
+		self.textures
+			.iter()
+			.map(|texture| SwapchainImageOpenGLKHR {
+				ty: SwapchainImageOpenGLKHR::TYPE,
+				next: ptr::null_mut(),
+				image: *texture,
+			})
+			.collect()
+	}
+	/// The dmabuf or shared memory behind each texture, in order.
+	pub fn fds(&self) -> Vec<BorrowedFd<'_>> {
+		match &self.sharing {
+			TextureSharing::Dmabuf { dmabufs, .. } => dmabufs.iter().map(AsFd::as_fd).collect(),
+			TextureSharing::Copy(memory) => memory.fds(),
+		}
+	}
+}
+impl Drop for OpenGLSwapchainImages {
+	fn drop(&mut self) {
+		// the app's context is current during xrDestroySwapchain, as with every GL swapchain call
+		unsafe {
+			if let TextureSharing::Dmabuf {
+				fns,
+				display,
+				egl_images,
+				..
+			} = &mut self.sharing
+			{
+				for egl_image in egl_images.drain(..) {
+					(fns.destroy_image)(*display, egl_image);
+				}
+			}
+			(self.fns.delete_textures)(self.textures.len() as i32, self.textures.as_ptr());
+		}
+	}
+}
+
+/// # Safety
+/// https://www.khronos.org/registry/OpenXR/specs/1.0/html/xrspec.html#xrGetOpenGLGraphicsRequirementsKHR
+#[no_mangle]
+pub unsafe extern "system" fn xrGetOpenGLGraphicsRequirementsKHR(
+	instance: Instance,
+	_system_id: SystemId,
+	graphics_requirements: &mut GraphicsRequirementsOpenGLKHR,
+) -> XrResult {
+	wrap_oxr! {
+		instance.get_stardust()?.graphics_requirements_queried.store(true, Ordering::Release);
+		graphics_requirements.min_api_version_supported = MIN_OPENGL_VERSION;
+		graphics_requirements.max_api_version_supported = MAX_OPENGL_VERSION;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::swapchain::tests::create_info;
+	use crate::swapchain::DRM_FORMAT_ARGB8888;
+	use std::{fs::File, io::Read, os::fd::AsRawFd};
+
+	const EGL_PLATFORM_SURFACELESS_MESA: u32 = 0x31DD;
+	const EGL_OPENGL_API: u32 = 0x30A2;
+	const EGL_CONTEXT_MAJOR_VERSION: i32 = 0x3098;
+	const EGL_CONTEXT_MINOR_VERSION: i32 = 0x30FB;
+	const EGL_CONTEXT_OPENGL_PROFILE_MASK: i32 = 0x30FD;
+	const EGL_CONTEXT_OPENGL_CORE_PROFILE_BIT: i32 = 1;
+	const GL_UNPACK_ALIGNMENT: u32 = 0x0CF5;
+
+	/// Make a surfaceless context current on this thread, which Mesa can do without a display, and with llvmpipe without a GPU.
+	unsafe fn make_context_current(get_proc_address: GetProcAddress) -> (EGLDisplay, EGLContext) {
+		let get_platform_display: unsafe extern "system" fn(
+			u32,
+			*mut c_void,
+			*const isize,
+		) -> EGLDisplay = load(get_proc_address, b"eglGetPlatformDisplay\0").unwrap();
+		let initialize: unsafe extern "system" fn(EGLDisplay, *mut i32, *mut i32) -> u32 =
+			load(get_proc_address, b"eglInitialize\0").unwrap();
+		let bind_api: unsafe extern "system" fn(u32) -> u32 =
+			load(get_proc_address, b"eglBindAPI\0").unwrap();
+		let create_context: unsafe extern "system" fn(
+			EGLDisplay,
+			*mut c_void,
+			EGLContext,
+			*const i32,
+		) -> EGLContext = load(get_proc_address, b"eglCreateContext\0").unwrap();
+
+		let display =
+			get_platform_display(EGL_PLATFORM_SURFACELESS_MESA, ptr::null_mut(), ptr::null());
+		assert!(
+			!display.is_null()
+				&& initialize(display, ptr::null_mut(), ptr::null_mut()) != 0
+				&& bind_api(EGL_OPENGL_API) != 0,
+			"no surfaceless EGL display, which needs Mesa"
+		);
+		let attributes = [
+			EGL_CONTEXT_MAJOR_VERSION,
+			4,
+			EGL_CONTEXT_MINOR_VERSION,
+			2,
+			EGL_CONTEXT_OPENGL_PROFILE_MASK,
+			EGL_CONTEXT_OPENGL_CORE_PROFILE_BIT,
+			EGL_NONE,
+		];
+		let context = create_context(
+			display,
+			ptr::null_mut(),
+			ptr::null_mut(),
+			attributes.as_ptr(),
+		);
+		assert!(!context.is_null(), "no OpenGL 4.2 core context");
+		make_current(get_proc_address, display, context);
+		(display, context)
+	}
+	unsafe fn make_current(
+		get_proc_address: GetProcAddress,
+		display: EGLDisplay,
+		context: EGLContext,
+	) {
+		let make_current: unsafe extern "system" fn(
+			EGLDisplay,
+			*mut c_void,
+			*mut c_void,
+			EGLContext,
+		) -> u32 = load(get_proc_address, b"eglMakeCurrent\0").unwrap();
+		assert_ne!(
+			make_current(display, ptr::null_mut(), ptr::null_mut(), context),
+			0
+		);
+	}
+
+	/// GLX contexts can't export anything through EGL, and neither can Mesa's software drivers.
+	#[test]
+	#[ignore = "needs Mesa's surfaceless EGL, which llvmpipe can do without a GPU"]
+	fn textures_are_only_exported_where_the_context_can() {
+		unsafe {
+			// the same binding the Xlib, XCB and Wayland ones get
+			let binding = OpenGLBinding::new().expect("no libEGL");
+			let (display, _) = make_context_current(binding.get_proc_address);
+			let fns = GlFns::load(binding.get_proc_address).unwrap();
+			let can_export = fns.can_export_dmabufs(display);
+			let (images, buffers) =
+				OpenGLSwapchainImages::new(&binding, &create_info(GL_RGBA8, 4, 4), 2).unwrap();
+			assert_eq!(
+				matches!(buffers, SwapchainBuffers::Dmabuf { .. }),
+				can_export,
+				"{buffers:?}"
+			);
+			assert_eq!(images.fds().len(), 2);
+			drop(images);
+
+			// nothing current through EGL, as with a GLX context
+			make_current(binding.get_proc_address, display, ptr::null_mut());
+			let (images, buffers) =
+				OpenGLSwapchainImages::new(&binding, &create_info(GL_RGBA8, 4, 4), 2).unwrap();
+			assert!(
+				matches!(buffers, SwapchainBuffers::Memory { .. }),
+				"{buffers:?}"
+			);
+			assert_eq!(images.fds().len(), 2);
+		}
+	}
+
+	#[test]
+	#[ignore = "needs a GPU whose EGL driver has EGL_MESA_image_dma_buf_export"]
+	fn textures_are_exported_as_dmabufs() {
+		unsafe {
+			let binding = OpenGLBinding::new().expect("no libEGL");
+			let (display, context) = make_context_current(binding.get_proc_address);
+			// as XR_MNDX_egl_enable would give it to us
+			let binding = OpenGLBinding {
+				egl: Some((display, context)),
+				..binding
+			};
+			let fns = GlFns::load(binding.get_proc_address).unwrap();
+			assert!(
+				fns.can_export_dmabufs(display),
+				"EGL_MESA_image_dma_buf_export isn't supported"
+			);
+
+			let (width, height) = (64, 32);
+			let (images, buffers) = OpenGLSwapchainImages::new(
+				&binding,
+				&create_info(GL_SRGB8_ALPHA8, width, height),
+				2,
+			)
+			.unwrap();
+			let SwapchainBuffers::Dmabuf {
+				fourcc,
+				srgb,
+				flip_y,
+				images: dmabuf_images,
+			} = buffers
+			else {
+				panic!("not dmabufs: {buffers:?}");
+			};
+			assert!([DRM_FORMAT_ABGR8888, DRM_FORMAT_ARGB8888].contains(&fourcc));
+			assert!(srgb);
+			assert!(flip_y);
+			let fds = images.fds();
+			assert_eq!(dmabuf_images.len(), 2);
+			assert_eq!(fds.len(), 2);
+			for (image, fd) in dmabuf_images.iter().zip(fds) {
+				assert!(image.stride >= width as u64 * 4);
+				// a dmabuf's size is wherever seeking to its end lands
+				let size = libc::lseek(fd.as_raw_fd(), 0, libc::SEEK_END);
+				assert!(size as u64 >= image.offset + image.stride * height as u64);
+			}
+		}
+	}
+
+	/// What GLX contexts get, as they can't export anything through EGL.
+	#[test]
+	#[ignore = "needs Mesa's surfaceless EGL, which llvmpipe can do without a GPU"]
+	fn released_textures_are_copied_to_shared_memory() {
+		unsafe {
+			let binding = OpenGLBinding::new().expect("no libEGL");
+			make_context_current(binding.get_proc_address);
+			let get_proc_address = binding.get_proc_address;
+			let tex_sub_image_2d: unsafe extern "system" fn(
+				u32,
+				i32,
+				i32,
+				i32,
+				i32,
+				i32,
+				u32,
+				u32,
+				*const c_void,
+			) = load(get_proc_address, b"glTexSubImage2D\0").unwrap();
+
+			let (width, height) = (4, 2);
+			let (images, buffers) = OpenGLSwapchainImages::new_copied(
+				GlFns::load(get_proc_address).unwrap(),
+				&create_info(GL_SRGB8_ALPHA8, width, height),
+				GL_SRGB8_ALPHA8,
+				2,
+			)
+			.unwrap();
+			let SwapchainBuffers::Memory {
+				fourcc, row_pitch, ..
+			} = buffers
+			else {
+				panic!("not shared memory: {buffers:?}");
+			};
+			assert_eq!(fourcc, DRM_FORMAT_ABGR8888);
+			assert_eq!(images.fds().len(), 2);
+
+			// red along the bottom row, which GL has first, and green along the top
+			let red = [255, 0, 0, 255];
+			let green = [0, 255, 0, 255];
+			let pixels: Vec<u8> = [red; 4].into_iter().chain([green; 4]).flatten().collect();
+			let fns = &images.fns;
+			(fns.pixel_storei)(GL_UNPACK_ALIGNMENT, 1);
+			fns.with_texture(images.textures[1], || {
+				tex_sub_image_2d(
+					GL_TEXTURE_2D,
+					0,
+					0,
+					0,
+					width as i32,
+					height as i32,
+					GL_RGBA,
+					GL_UNSIGNED_BYTE,
+					pixels.as_ptr() as *const c_void,
+				)
+			});
+			// the app's pack state, which the copy mustn't trip over or clobber
+			(fns.pixel_storei)(GL_PACK_ROW_LENGTH, 7);
+			(fns.pixel_storei)(GL_PACK_ALIGNMENT, 1);
+
+			images.released(1);
+
+			let mut pack_row_length = 0;
+			(fns.get_integerv)(GL_PACK_ROW_LENGTH, &mut pack_row_length);
+			assert_eq!(pack_row_length, 7);
+
+			let mut data = Vec::new();
+			File::from(images.fds()[1].try_clone_to_owned().unwrap())
+				.read_to_end(&mut data)
+				.unwrap();
+			let row = |index: usize| &data[index * row_pitch as usize..][..width as usize * 4];
+			assert_eq!(row(0), [green; 4].concat());
+			assert_eq!(row(1), [red; 4].concat());
+		}
+	}
+}
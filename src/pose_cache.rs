@@ -0,0 +1,651 @@
+use crate::{
+	space::{StardustSpaceLocation, StardustViewLocations},
+	util::{delay_from_time, now},
+	XrResult,
+};
+use glam::{Quat, Vec3};
+use openxr_sys::{
+	Fovf, Posef, SpaceLocationFlags, SpaceVelocityFlags, Time, Vector3f, ViewConfigurationType,
+	ViewStateFlags,
+};
+use rustc_hash::FxHashMap;
+use serde::Deserialize;
+use std::collections::VecDeque;
+
+/// How far past now poses can be asked for, well beyond any sensible prediction.
+const MAX_PREDICTION: i64 = 500_000_000;
+/// How long poses are remembered, and so how far back from now they can be asked for.
+const HISTORY_DURATION: i64 = 1_000_000_000;
+/// How far apart the two poses velocity is worked out from are, short enough to not smooth over anything.
+const VELOCITY_INTERVAL: i64 = 1_000_000;
+
+/// Whether poses can be given for `time` at all, which has to be somewhere around now.
+pub fn validate_time(time: Time) -> Result<(), XrResult> {
+	validate_time_at(time, now())
+}
+fn validate_time_at(time: Time, now: Time) -> Result<(), XrResult> {
+	let (time, now) = (time.as_nanos(), now.as_nanos());
+	if time <= 0 || time < now - HISTORY_DURATION || time > now + MAX_PREDICTION {
+		return Err(XrResult::ERROR_TIME_INVALID);
+	}
+	Ok(())
+}
+
+/// Everything the session can locate, sampled by the server at the times we asked for, relative to the session's root.
+#[derive(Debug, Deserialize)]
+pub struct StardustPoseSnapshot {
+	/// Each of the session's spaces by node path, with one sample per requested time, `None` where it couldn't be located
+	pub spaces: FxHashMap<String, Vec<Option<StardustSpaceLocation>>>,
+	/// The views of the view configuration the session was begun with, one sample per requested time
+	pub views: Vec<StardustViewLocations>,
+}
+
+/// A pose as a rotation and translation, for doing math on.
+#[derive(Debug, Clone, Copy)]
+struct Transform {
+	position: Vec3,
+	orientation: Quat,
+}
+impl Transform {
+	const IDENTITY: Self = Transform {
+		position: Vec3::ZERO,
+		orientation: Quat::IDENTITY,
+	};
+
+	fn inverse(self) -> Self {
+		let orientation = self.orientation.inverse();
+		Transform {
+			position: orientation * -self.position,
+			orientation,
+		}
+	}
+	fn mul(self, other: Self) -> Self {
+		Transform {
+			position: self.position + self.orientation * other.position,
+			orientation: (self.orientation * other.orientation).normalize(),
+		}
+	}
+	fn lerp(self, other: Self, t: f32) -> Self {
+		Transform {
+			position: self.position.lerp(other.position, t),
+			orientation: self.orientation.slerp(other.orientation, t),
+		}
+	}
+	/// Linear and angular velocity in m/s and rad/s, for getting from `self` to `other` in `seconds`.
+	fn velocity_to(self, other: Self, seconds: f32) -> (Vec3, Vec3) {
+		let mut rotation = other.orientation * self.orientation.inverse();
+		// the short way round
+		if rotation.w < 0.0 {
+			rotation = -rotation;
+		}
+		// rotations between poses this close are tiny, which acos of w can't resolve in an f32 but atan2 can
+		let axis = Vec3::new(rotation.x, rotation.y, rotation.z);
+		let sin_half_angle = axis.length();
+		let rotation = if sin_half_angle > 0.0 {
+			axis / sin_half_angle * 2.0 * sin_half_angle.atan2(rotation.w)
+		} else {
+			Vec3::ZERO
+		};
+		(
+			(other.position - self.position) / seconds,
+			rotation / seconds,
+		)
+	}
+	/// Where this would be after moving at a constant velocity for `seconds`, which can be negative.
+	fn extrapolate(self, linear: Vec3, angular: Vec3, seconds: f32) -> Self {
+		Transform {
+			position: self.position + linear * seconds,
+			orientation: (Quat::from_scaled_axis(angular * seconds) * self.orientation).normalize(),
+		}
+	}
+}
+impl From<Posef> for Transform {
+	fn from(pose: Posef) -> Self {
+		let position: mint::Vector3<f32> = pose.position.into();
+		let orientation: mint::Quaternion<f32> = pose.orientation.into();
+		Transform {
+			position: position.into(),
+			orientation: Quat::from(orientation).normalize(),
+		}
+	}
+}
+impl From<Transform> for Posef {
+	fn from(transform: Transform) -> Self {
+		let position: mint::Vector3<f32> = transform.position.into();
+		let orientation: mint::Quaternion<f32> = transform.orientation.into();
+		Posef {
+			orientation: orientation.into(),
+			position: position.into(),
+		}
+	}
+}
+
+/// Valid or tracked only if both ends of a relation are.
+fn combine_flags(a: SpaceLocationFlags, b: SpaceLocationFlags) -> SpaceLocationFlags {
+	SpaceLocationFlags::from_raw(a.into_raw() & b.into_raw())
+}
+fn view_state_flags(flags: SpaceLocationFlags) -> ViewStateFlags {
+	// the two share bit values
+	ViewStateFlags::from_raw(flags.into_raw())
+}
+fn space_location_flags(flags: ViewStateFlags) -> SpaceLocationFlags {
+	SpaceLocationFlags::from_raw(flags.into_raw())
+}
+/// Whether a sample's pose means anything, and so is worth remembering.
+fn located(flags: SpaceLocationFlags) -> bool {
+	flags.contains(SpaceLocationFlags::POSITION_VALID | SpaceLocationFlags::ORIENTATION_VALID)
+}
+fn vector(vector: Vec3) -> Vector3f {
+	let vector: mint::Vector3<f32> = vector.into();
+	vector.into()
+}
+
+/// How fast something's moving at `time` in m/s and rad/s, going by where `locate` puts it a moment either side.
+fn velocity_at(
+	time: Time,
+	locate: impl Fn(Time) -> Option<(SpaceLocationFlags, Transform)>,
+) -> Option<(SpaceVelocityFlags, Vector3f, Vector3f)> {
+	let (before_flags, before) = locate(Time::from_nanos(time.as_nanos() - VELOCITY_INTERVAL / 2))?;
+	let (after_flags, after) = locate(Time::from_nanos(time.as_nanos() + VELOCITY_INTERVAL / 2))?;
+	let flags = combine_flags(before_flags, after_flags);
+	let (linear, angular) = before.velocity_to(after, VELOCITY_INTERVAL as f32 / 1e9);
+	let mut velocity_flags = SpaceVelocityFlags::EMPTY;
+	if flags.contains(SpaceLocationFlags::POSITION_VALID) {
+		velocity_flags |= SpaceVelocityFlags::LINEAR_VALID;
+	}
+	if flags.contains(SpaceLocationFlags::ORIENTATION_VALID) {
+		velocity_flags |= SpaceVelocityFlags::ANGULAR_VALID;
+	}
+	Some((velocity_flags, vector(linear), vector(angular)))
+}
+
+#[derive(Debug, Clone)]
+struct ViewSample {
+	flags: SpaceLocationFlags,
+	views: Vec<(Transform, Fovf)>,
+}
+
+/// Where everything in a session was around the frame being rendered, so locating doesn't need a round trip to the server per call.
+///
+/// Fetched once per xrWaitFrame, and only answers for times between its first and last samples, interpolating in between.
+#[derive(Debug)]
+pub struct PoseCache {
+	times: Vec<Time>,
+	spaces: FxHashMap<String, Vec<Option<(SpaceLocationFlags, Transform)>>>,
+	view_configuration_type: ViewConfigurationType,
+	views: Vec<ViewSample>,
+}
+impl PoseCache {
+	/// The snapshot's samples have to line up with `times`, which have to be strictly increasing.
+	pub fn new(
+		times: Vec<Time>,
+		view_configuration_type: ViewConfigurationType,
+		snapshot: StardustPoseSnapshot,
+	) -> Result<Self, XrResult> {
+		if !times.windows(2).all(|t| t[0].as_nanos() < t[1].as_nanos())
+			|| snapshot.views.len() != times.len()
+			|| snapshot.spaces.values().any(|s| s.len() != times.len())
+		{
+			return Err(XrResult::ERROR_RUNTIME_FAILURE);
+		}
+		let spaces = snapshot
+			.spaces
+			.into_iter()
+			.map(|(node_path, samples)| {
+				let samples = samples
+					.into_iter()
+					.map(|s| s.map(|s| (s.flags(), Posef::from(s.pose).into())))
+					.collect();
+				(node_path, samples)
+			})
+			.collect();
+		let views = snapshot
+			.views
+			.into_iter()
+			.map(|v| ViewSample {
+				flags: space_location_flags(v.flags()),
+				views: v
+					.views
+					.into_iter()
+					.map(|v| (Posef::from(v.pose).into(), v.fov.into()))
+					.collect(),
+			})
+			.collect();
+		Ok(PoseCache {
+			times,
+			spaces,
+			view_configuration_type,
+			views,
+		})
+	}
+
+	/// The delays to ask the server to sample at, matching up with `times`.
+	pub fn sample_delays(times: &[Time]) -> Vec<f64> {
+		times.iter().copied().map(delay_from_time).collect()
+	}
+
+	/// The two samples either side of `time` and how far between them it is, if it's within the cache.
+	fn bracket(&self, time: Time) -> Option<(usize, usize, f32)> {
+		let time = time.as_nanos();
+		let after = self.times.iter().position(|t| t.as_nanos() >= time)?;
+		if after == 0 {
+			return (self.times[0].as_nanos() == time).then_some((0, 0, 0.0));
+		}
+		let before = after - 1;
+		let start = self.times[before].as_nanos();
+		let end = self.times[after].as_nanos();
+		Some((before, after, (time - start) as f32 / (end - start) as f32))
+	}
+	fn sample_space(
+		&self,
+		node_path: &str,
+		time: Time,
+	) -> Option<Option<(SpaceLocationFlags, Transform)>> {
+		let (before, after, t) = self.bracket(time)?;
+		let samples = self.spaces.get(node_path)?;
+		Some(match (samples[before], samples[after]) {
+			(Some((flags_before, before)), Some((flags_after, after))) => Some((
+				combine_flags(flags_before, flags_after),
+				before.lerp(after, t),
+			)),
+			_ => None,
+		})
+	}
+
+	fn relative(
+		&self,
+		node_path: &str,
+		base_node_path: &str,
+		time: Time,
+	) -> Option<(SpaceLocationFlags, Transform)> {
+		let space = self.sample_space(node_path, time)?;
+		let base = self.sample_space(base_node_path, time)?;
+		Some(match (space, base) {
+			(Some((space_flags, space)), Some((base_flags, base))) => (
+				combine_flags(space_flags, base_flags),
+				base.inverse().mul(space),
+			),
+			_ => (SpaceLocationFlags::EMPTY, Transform::IDENTITY),
+		})
+	}
+
+	/// Where the space at `node_path` is relative to the one at `base_node_path` at `time`, or `None` if the cache can't say.
+	pub fn locate_space(
+		&self,
+		node_path: &str,
+		base_node_path: &str,
+		time: Time,
+	) -> Option<(SpaceLocationFlags, Posef)> {
+		let (flags, transform) = self.relative(node_path, base_node_path, time)?;
+		Some((flags, transform.into()))
+	}
+	/// How fast the space at `node_path` is moving relative to the one at `base_node_path` at `time`, or `None` if the cache can't say.
+	pub fn space_velocity(
+		&self,
+		node_path: &str,
+		base_node_path: &str,
+		time: Time,
+	) -> Option<(SpaceVelocityFlags, Vector3f, Vector3f)> {
+		velocity_at(time, |time| self.relative(node_path, base_node_path, time))
+	}
+	/// Where the views are relative to the space at `base_node_path` at `time`, or `None` if the cache can't say.
+	pub fn locate_views(
+		&self,
+		view_configuration_type: ViewConfigurationType,
+		base_node_path: &str,
+		time: Time,
+	) -> Option<(ViewStateFlags, Vec<(Posef, Fovf)>)> {
+		if view_configuration_type != self.view_configuration_type {
+			return None;
+		}
+		let (before, after, t) = self.bracket(time)?;
+		let base = self.sample_space(base_node_path, time)?;
+		let (before, after) = (&self.views[before], &self.views[after]);
+		if before.views.len() != after.views.len() {
+			return None;
+		}
+		let Some((base_flags, base)) = base else {
+			let views = before.views.iter().map(|(_, fov)| (Posef::IDENTITY, *fov));
+			return Some((ViewStateFlags::EMPTY, views.collect()));
+		};
+		let flags = combine_flags(combine_flags(before.flags, after.flags), base_flags);
+		let base = base.inverse();
+		let views = before
+			.views
+			.iter()
+			.zip(&after.views)
+			.map(|((pose_before, fov_before), (pose_after, fov_after))| {
+				let pose = base.mul(pose_before.lerp(*pose_after, t)).into();
+				// the field of view barely changes within a frame, so the nearer sample does
+				(pose, if t < 0.5 { *fov_before } else { *fov_after })
+			})
+			.collect();
+		Some((view_state_flags(flags), views))
+	}
+}
+
+/// Timestamped poses of one thing, oldest first.
+#[derive(Debug, Default)]
+struct Track(VecDeque<(Time, SpaceLocationFlags, Transform)>);
+impl Track {
+	fn push(&mut self, time: Time, flags: SpaceLocationFlags, transform: Transform) {
+		if self
+			.0
+			.back()
+			.is_some_and(|(last, _, _)| last.as_nanos() >= time.as_nanos())
+		{
+			return;
+		}
+		self.0.push_back((time, flags, transform));
+		while self
+			.0
+			.front()
+			.is_some_and(|(oldest, _, _)| oldest.as_nanos() < time.as_nanos() - HISTORY_DURATION)
+		{
+			self.0.pop_front();
+		}
+	}
+	/// Whether there's enough of the track to tell how it's moving, rather than just where it is.
+	fn has_velocity(&self) -> bool {
+		self.0.len() >= 2
+	}
+	/// Where this was or will be at `time`, assuming it moves at a constant velocity between the two samples nearest it.
+	///
+	/// In between samples that's just interpolating, and past either end it carries on the motion the samples there show.
+	fn sample(&self, time: Time) -> Option<(SpaceLocationFlags, Transform)> {
+		let &(_, last_flags, last) = self.0.back()?;
+		if self.0.len() == 1 {
+			return Some((last_flags, last));
+		}
+		let after = self
+			.0
+			.iter()
+			.position(|(t, _, _)| t.as_nanos() >= time.as_nanos())
+			.unwrap_or(self.0.len() - 1)
+			.max(1);
+		let (before_time, before_flags, before) = self.0[after - 1];
+		let (after_time, after_flags, after) = self.0[after];
+		let (linear, angular) = before.velocity_to(
+			after,
+			(after_time.as_nanos() - before_time.as_nanos()) as f32 / 1e9,
+		);
+		let seconds = (time.as_nanos() - after_time.as_nanos()) as f32 / 1e9;
+		Some((
+			combine_flags(before_flags, after_flags),
+			after.extrapolate(linear, angular, seconds),
+		))
+	}
+}
+
+/// The last second or so of where everything in a session was, to predict poses at times the pose cache doesn't cover.
+///
+/// Fed the current sample of every frame's pose cache, so it only knows what's actually been tracked rather than what the server predicted.
+#[derive(Debug, Default)]
+pub struct PoseHistory {
+	spaces: FxHashMap<String, Track>,
+	view_configuration_type: Option<ViewConfigurationType>,
+	views: Vec<Track>,
+	fovs: Vec<Fovf>,
+}
+impl PoseHistory {
+	/// Remember the earliest sample of `cache`, which is where everything was when it was fetched.
+	pub fn record(&mut self, cache: &PoseCache) {
+		let (Some(&time), Some(view_sample)) = (cache.times.first(), cache.views.first()) else {
+			return;
+		};
+		// spaces missing from the snapshot have been destroyed
+		self.spaces
+			.retain(|node_path, _| cache.spaces.contains_key(node_path));
+		for (node_path, samples) in &cache.spaces {
+			if let Some((flags, transform)) = samples[0].filter(|(flags, _)| located(*flags)) {
+				self.spaces
+					.entry(node_path.clone())
+					.or_default()
+					.push(time, flags, transform);
+			}
+		}
+
+		if self.view_configuration_type != Some(cache.view_configuration_type)
+			|| self.views.len() != view_sample.views.len()
+		{
+			self.view_configuration_type = Some(cache.view_configuration_type);
+			self.views = view_sample.views.iter().map(|_| Track::default()).collect();
+		}
+		if located(view_sample.flags) {
+			for (track, (transform, _)) in self.views.iter_mut().zip(&view_sample.views) {
+				track.push(time, view_sample.flags, *transform);
+			}
+		}
+		self.fovs = view_sample.views.iter().map(|(_, fov)| *fov).collect();
+	}
+
+	fn relative(
+		&self,
+		node_path: &str,
+		base_node_path: &str,
+		time: Time,
+	) -> Option<(SpaceLocationFlags, Transform)> {
+		let (space_flags, space) = self.spaces.get(node_path)?.sample(time)?;
+		let (base_flags, base) = self.spaces.get(base_node_path)?.sample(time)?;
+		Some((
+			combine_flags(space_flags, base_flags),
+			base.inverse().mul(space),
+		))
+	}
+
+	/// Where the space at `node_path` is relative to the one at `base_node_path` at `time`, or `None` if either hasn't been seen.
+	pub fn locate_space(
+		&self,
+		node_path: &str,
+		base_node_path: &str,
+		time: Time,
+	) -> Option<(SpaceLocationFlags, Posef)> {
+		let (flags, transform) = self.relative(node_path, base_node_path, time)?;
+		Some((flags, transform.into()))
+	}
+	/// How fast the space at `node_path` is moving relative to the one at `base_node_path` at `time`, or `None` if either hasn't been seen.
+	///
+	/// A space seen only once is somewhere, but not moving at any velocity we know of.
+	pub fn space_velocity(
+		&self,
+		node_path: &str,
+		base_node_path: &str,
+		time: Time,
+	) -> Option<(SpaceVelocityFlags, Vector3f, Vector3f)> {
+		let (space, base) = (
+			self.spaces.get(node_path)?,
+			self.spaces.get(base_node_path)?,
+		);
+		if !space.has_velocity() || !base.has_velocity() {
+			return Some((
+				SpaceVelocityFlags::EMPTY,
+				vector(Vec3::ZERO),
+				vector(Vec3::ZERO),
+			));
+		}
+		velocity_at(time, |time| self.relative(node_path, base_node_path, time))
+	}
+	/// Where the views are relative to the space at `base_node_path` at `time`, or `None` if they haven't been seen.
+	pub fn locate_views(
+		&self,
+		view_configuration_type: ViewConfigurationType,
+		base_node_path: &str,
+		time: Time,
+	) -> Option<(ViewStateFlags, Vec<(Posef, Fovf)>)> {
+		if self.view_configuration_type != Some(view_configuration_type) {
+			return None;
+		}
+		let (mut flags, base) = self.spaces.get(base_node_path)?.sample(time)?;
+		let base = base.inverse();
+		let views = self
+			.views
+			.iter()
+			.zip(&self.fovs)
+			.map(|(track, fov)| {
+				let (view_flags, view) = track.sample(time)?;
+				flags = combine_flags(flags, view_flags);
+				Some((base.mul(view).into(), *fov))
+			})
+			.collect::<Option<Vec<_>>>()?;
+		Some((view_state_flags(flags), views))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn located() -> SpaceLocationFlags {
+		SpaceLocationFlags::POSITION_VALID | SpaceLocationFlags::ORIENTATION_VALID
+	}
+
+	fn time(seconds: f64) -> Time {
+		Time::from_nanos((seconds * 1e9).round() as i64)
+	}
+	fn transform(position: Vec3, orientation: Quat) -> Transform {
+		Transform {
+			position,
+			orientation,
+		}
+	}
+	fn track(samples: &[(f64, Transform)]) -> Track {
+		let mut track = Track::default();
+		for (seconds, transform) in samples {
+			track.push(time(*seconds), located(), *transform);
+		}
+		track
+	}
+	fn assert_near(a: Vec3, b: Vec3) {
+		assert!(a.distance(b) < 1e-3, "{a} isn't {b}");
+	}
+	fn assert_same_rotation(a: Quat, b: Quat) {
+		// q and -q are the same rotation
+		assert!(a.dot(b).abs() > 1.0 - 1e-6, "{a} isn't {b}");
+	}
+
+	#[test]
+	fn track_extrapolates_constant_velocity() {
+		let track = track(&[
+			(10.0, transform(Vec3::ZERO, Quat::IDENTITY)),
+			(10.1, transform(Vec3::new(0.1, 0.0, -0.2), Quat::IDENTITY)),
+		]);
+		let position = |seconds| track.sample(time(seconds)).unwrap().1.position;
+		assert_near(position(10.05), Vec3::new(0.05, 0.0, -0.1));
+		assert_near(position(10.3), Vec3::new(0.3, 0.0, -0.6));
+		assert_near(position(9.9), Vec3::new(-0.1, 0.0, 0.2));
+	}
+
+	#[test]
+	fn track_extrapolates_constant_rotation() {
+		let track = track(&[
+			(10.0, transform(Vec3::ZERO, Quat::from_rotation_y(0.1))),
+			(10.1, transform(Vec3::ZERO, Quat::from_rotation_y(0.2))),
+		]);
+		let (flags, predicted) = track.sample(time(10.3)).unwrap();
+		assert_eq!(flags, located());
+		assert_same_rotation(predicted.orientation, Quat::from_rotation_y(0.4));
+		assert_near(predicted.position, Vec3::ZERO);
+	}
+
+	#[test]
+	fn track_rotates_the_short_way_round() {
+		// the same orientation as a quaternion with w < 0, which is the long way round without flipping it
+		let track = track(&[
+			(10.0, transform(Vec3::ZERO, Quat::from_rotation_y(0.1))),
+			(10.1, transform(Vec3::ZERO, -Quat::from_rotation_y(0.2))),
+		]);
+		// half an interval on, where going the long way round ends up half a turn off rather than a whole turn
+		let (_, predicted) = track.sample(time(10.15)).unwrap();
+		assert_same_rotation(predicted.orientation, Quat::from_rotation_y(0.25));
+	}
+
+	#[test]
+	fn track_with_one_sample_stays_put() {
+		let only = transform(Vec3::new(1.0, 1.6, -0.5), Quat::from_rotation_x(0.3));
+		let track = track(&[(10.0, only)]);
+		for seconds in [9.5, 10.0, 10.5] {
+			let (flags, sampled) = track.sample(time(seconds)).unwrap();
+			assert_eq!(flags, located());
+			assert_near(sampled.position, only.position);
+			assert_same_rotation(sampled.orientation, only.orientation);
+		}
+		assert!(Track::default().sample(time(10.0)).is_none());
+	}
+
+	#[test]
+	fn velocity_of_known_motion() {
+		let linear = Vec3::new(1.0, 0.0, -2.0);
+		let angular = Vec3::new(0.0, 3.0, 0.0);
+		let moving = |t: Time| {
+			let seconds = ((t.as_nanos() - time(10.0).as_nanos()) as f64 / 1e9) as f32;
+			let pose = transform(linear * seconds, Quat::from_scaled_axis(angular * seconds));
+			Some((located(), pose))
+		};
+		let (flags, measured_linear, measured_angular) = velocity_at(time(10.0), moving).unwrap();
+		assert_eq!(
+			flags,
+			SpaceVelocityFlags::LINEAR_VALID | SpaceVelocityFlags::ANGULAR_VALID
+		);
+		let measured_linear = Vec3::new(measured_linear.x, measured_linear.y, measured_linear.z);
+		let measured_angular =
+			Vec3::new(measured_angular.x, measured_angular.y, measured_angular.z);
+		assert!(measured_linear.distance(linear) < 1e-2, "{measured_linear}");
+		assert!(
+			measured_angular.distance(angular) < 1e-2,
+			"{measured_angular}"
+		);
+	}
+
+	#[test]
+	fn velocity_needs_two_samples() {
+		let still = transform(Vec3::ZERO, Quat::IDENTITY);
+		let mut history = PoseHistory::default();
+		history
+			.spaces
+			.insert("base".to_string(), track(&[(10.0, still), (10.1, still)]));
+		history
+			.spaces
+			.insert("space".to_string(), track(&[(10.1, still)]));
+
+		let (flags, _, _) = history.space_velocity("space", "base", time(10.1)).unwrap();
+		assert_eq!(flags, SpaceVelocityFlags::EMPTY);
+		// still located, there's just no telling how it's moving
+		let (flags, _) = history.locate_space("space", "base", time(10.1)).unwrap();
+		assert_eq!(flags, located());
+
+		history
+			.spaces
+			.get_mut("space")
+			.unwrap()
+			.push(time(10.2), located(), still);
+		let (flags, _, _) = history.space_velocity("space", "base", time(10.2)).unwrap();
+		assert_eq!(
+			flags,
+			SpaceVelocityFlags::LINEAR_VALID | SpaceVelocityFlags::ANGULAR_VALID
+		);
+	}
+
+	#[test]
+	fn times_outside_the_window_are_invalid() {
+		let now = time(10.0);
+		let from_now = |nanos: i64| Time::from_nanos(now.as_nanos() + nanos);
+		for valid in [0, MAX_PREDICTION, -HISTORY_DURATION] {
+			assert_eq!(validate_time_at(from_now(valid), now), Ok(()));
+		}
+		for invalid in [MAX_PREDICTION + 1, -HISTORY_DURATION - 1] {
+			assert_eq!(
+				validate_time_at(from_now(invalid), now),
+				Err(XrResult::ERROR_TIME_INVALID)
+			);
+		}
+		// just after startup the window reaches back past 0, which is never a valid time
+		let startup = Time::from_nanos(1);
+		assert_eq!(validate_time_at(startup, startup), Ok(()));
+		for invalid in [0, -1] {
+			assert_eq!(
+				validate_time_at(Time::from_nanos(invalid), startup),
+				Err(XrResult::ERROR_TIME_INVALID)
+			);
+		}
+	}
+}
@@ -1,47 +1,376 @@
 use crate::{
+	events::EventQueue,
+	frame::FrameLoop,
+	graphics::GraphicsBinding,
+	handle::{Handle, HandleType, Handles, Node},
 	instance::StardustInstance,
 	oxr::{Instance, Session, SessionCreateInfo},
-	util::{get_next_chain, Handle},
+	pose_cache::{PoseCache, PoseHistory},
+	system::{environment_blend_modes, max_layer_count, view_count},
+	util::{lock, now},
 	XrResult,
 };
-use openxr_sys::SystemId;
+use openxr_sys::{
+	EnvironmentBlendMode, EventDataSessionStateChanged, Fovf, Posef, SessionBeginInfo,
+	SessionState, SpaceLocationFlags, SpaceVelocityFlags, SystemId, Time, Vector3f,
+	ViewConfigurationType, ViewStateFlags,
+};
+use slotmap::{DefaultKey, SecondaryMap};
+use std::{
+	ptr,
+	sync::{Arc, Mutex, MutexGuard},
+};
 
 impl Handle for Session {
 	type StardustType = StardustSession;
+	const TYPE: HandleType = HandleType::Session;
 
 	fn raw(&self) -> u64 {
 		self.into_raw()
 	}
+	fn from_raw(raw: u64) -> Self {
+		Session::from_raw(raw)
+	}
+	fn table(handles: &mut Handles) -> &mut SecondaryMap<DefaultKey, Arc<StardustSession>> {
+		&mut handles.sessions
+	}
+	fn check_lost(stardust: &StardustSession) -> Result<(), XrResult> {
+		if stardust.lost() {
+			return Err(XrResult::ERROR_SESSION_LOST);
+		}
+		Ok(())
+	}
+}
+impl Node for StardustSession {
+	fn server_node_path(&self) -> Option<&str> {
+		Some(&self.node_path)
+	}
+}
+
+/// The states a running session moves through, in order, on its way to focus.
+const RUNNING_STATES: [SessionState; 3] = [
+	SessionState::SYNCHRONIZED,
+	SessionState::VISIBLE,
+	SessionState::FOCUSED,
+];
+fn running_index(state: SessionState) -> Option<usize> {
+	RUNNING_STATES.iter().position(|s| *s == state)
+}
+
+/// Where a session is in its lifecycle, which the app's threads and server events all move along.
+struct SessionLifecycle {
+	/// Null until the session's handle is made, which is when it goes idle
+	session: Session,
+	/// The instance's event queue, which every state change goes straight onto so it's in order with everything else
+	events: Arc<Mutex<EventQueue>>,
+	state: SessionState,
+	running: bool,
+	exit_requested: bool,
+	view_configuration_type: ViewConfigurationType,
+	environment_blend_modes: Vec<EnvironmentBlendMode>,
+}
+impl SessionLifecycle {
+	fn set_state(&mut self, state: SessionState) {
+		if self.state == state {
+			return;
+		}
+		self.state = state;
+		lock(&self.events).push(EventDataSessionStateChanged {
+			ty: EventDataSessionStateChanged::TYPE,
+			next: ptr::null(),
+			session: self.session,
+			state,
+			time: now(),
+		});
+	}
+	/// Step through the running states one at a time so the app sees every transition.
+	fn step_running_state(&mut self, target: SessionState) {
+		let target_index = running_index(target).unwrap();
+		let mut current_index = match running_index(self.state) {
+			Some(index) => index,
+			None => {
+				self.set_state(SessionState::SYNCHRONIZED);
+				0
+			}
+		};
+		while current_index != target_index {
+			current_index = if current_index < target_index {
+				current_index + 1
+			} else {
+				current_index - 1
+			};
+			self.set_state(RUNNING_STATES[current_index]);
+		}
+	}
+	fn stop(&mut self) {
+		if running_index(self.state).is_some() {
+			self.step_running_state(SessionState::SYNCHRONIZED);
+		}
+		self.set_state(SessionState::STOPPING);
+	}
 }
 
 pub struct StardustSession {
 	instance: Instance,
+	system: SystemId,
 	node_path: String,
+	graphics: GraphicsBinding,
+	/// The system's `max_layer_count` when the session was created, which xrEndFrame holds the app to.
+	max_layer_count: u32,
+	lifecycle: Mutex<SessionLifecycle>,
+	frame_loop: Mutex<FrameLoop>,
+	pose_cache: Mutex<Option<Arc<PoseCache>>>,
+	pose_history: Mutex<PoseHistory>,
 }
 impl StardustSession {
-	fn new(instance: Instance, system: SystemId) -> Result<Self, XrResult> {
+	fn new(
+		instance: Instance,
+		system: SystemId,
+		graphics: GraphicsBinding,
+	) -> Result<Self, XrResult> {
 		let id = nanoid::nanoid!();
-		{
-			let instance = instance.get_stardust()?;
-			instance.send_signal(
-				&format!("/openxr/system{}", system.into_raw()),
-				"create_session",
-				&id,
-			)?;
-		}
+		let stardust_instance = instance.get_stardust()?;
+		let max_layer_count = max_layer_count(&stardust_instance, system)?;
+		stardust_instance.send_signal(
+			&format!("/openxr/system{}", system.into_raw()),
+			"create_session",
+			&id,
+		)?;
 
-		let session = StardustSession {
+		let lifecycle = SessionLifecycle {
+			session: Session::NULL,
+			events: stardust_instance.events(),
+			state: SessionState::UNKNOWN,
+			running: false,
+			exit_requested: false,
+			view_configuration_type: ViewConfigurationType::PRIMARY_STEREO,
+			environment_blend_modes: Vec::new(),
+		};
+		Ok(StardustSession {
 			instance,
+			system,
 			node_path: format!("/openxr/system{}/{}", system.into_raw(), id),
-		};
-		Ok(session)
+			graphics,
+			max_layer_count,
+			lifecycle: Mutex::new(lifecycle),
+			frame_loop: Mutex::new(FrameLoop::new(graphics.is_headless())),
+			pose_cache: Mutex::new(None),
+			pose_history: Mutex::new(PoseHistory::default()),
+		})
+	}
+	/// The session has a handle now, so it can go idle and tell the app about it.
+	fn created(&self, session: Session) {
+		let mut lifecycle = lock(&self.lifecycle);
+		lifecycle.session = session;
+		lifecycle.set_state(SessionState::IDLE);
+	}
+	/// Whether the session went with the server, either as the server told us or because the connection's gone.
+	pub fn lost(&self) -> bool {
+		self.state() == SessionState::LOSS_PENDING
+			|| self
+				.instance
+				.get_stardust_even_if_lost()
+				.map_or(true, |instance| instance.lost())
 	}
-	pub fn instance<'a>(&'a mut self) -> Result<&'a mut StardustInstance, XrResult> {
+	/// The instance, to talk to the server through, which a lost session can't do anymore.
+	pub fn instance(&self) -> Result<Arc<StardustInstance>, XrResult> {
+		if self.lost() {
+			return Err(XrResult::ERROR_SESSION_LOST);
+		}
 		self.instance.get_stardust()
 	}
 	pub fn node_path(&self) -> &str {
 		&self.node_path
 	}
+	pub fn max_layer_count(&self) -> u32 {
+		self.max_layer_count
+	}
+	pub fn system_node_path(&self) -> String {
+		format!("/openxr/system{}", self.system.into_raw())
+	}
+	pub fn state(&self) -> SessionState {
+		lock(&self.lifecycle).state
+	}
+	pub fn running(&self) -> bool {
+		lock(&self.lifecycle).running
+	}
+	pub fn graphics(&self) -> &GraphicsBinding {
+		&self.graphics
+	}
+	/// The frame loop, which shouldn't be held on to across a call to the server.
+	pub fn frame_loop(&self) -> MutexGuard<'_, FrameLoop> {
+		lock(&self.frame_loop)
+	}
+	/// The primary view configuration the session was last begun with.
+	pub fn view_configuration_type(&self) -> ViewConfigurationType {
+		lock(&self.lifecycle).view_configuration_type
+	}
+	/// What the server can composite the session's view configuration with, queried when it was begun.
+	pub fn environment_blend_modes(&self) -> Vec<EnvironmentBlendMode> {
+		lock(&self.lifecycle).environment_blend_modes.clone()
+	}
+	/// Poses for the frame the app's working on, if they've been fetched.
+	pub fn pose_cache(&self) -> Option<Arc<PoseCache>> {
+		lock(&self.pose_cache).clone()
+	}
+	pub fn set_pose_cache(&self, pose_cache: Option<PoseCache>) {
+		if let Some(pose_cache) = &pose_cache {
+			lock(&self.pose_history).record(pose_cache);
+		}
+		*lock(&self.pose_cache) = pose_cache.map(Arc::new);
+	}
+	/// Where the space at `node_path` is relative to the one at `base_node_path` at `time`, without asking the server.
+	///
+	/// This frame's poses are used if they cover `time`, otherwise recent ones are extrapolated to it.
+	pub fn locate_space_locally(
+		&self,
+		node_path: &str,
+		base_node_path: &str,
+		time: Time,
+	) -> Option<(SpaceLocationFlags, Posef)> {
+		self.pose_cache()
+			.and_then(|cache| cache.locate_space(node_path, base_node_path, time))
+			.or_else(|| lock(&self.pose_history).locate_space(node_path, base_node_path, time))
+	}
+	/// How fast the space at `node_path` is moving relative to the one at `base_node_path` at `time`, from the same poses as [`Self::locate_space_locally`].
+	pub fn space_velocity_locally(
+		&self,
+		node_path: &str,
+		base_node_path: &str,
+		time: Time,
+	) -> Option<(SpaceVelocityFlags, Vector3f, Vector3f)> {
+		self.pose_cache()
+			.and_then(|cache| cache.space_velocity(node_path, base_node_path, time))
+			.or_else(|| lock(&self.pose_history).space_velocity(node_path, base_node_path, time))
+	}
+	/// Where the views are relative to the space at `base_node_path` at `time`, without asking the server.
+	pub fn locate_views_locally(
+		&self,
+		view_configuration_type: ViewConfigurationType,
+		base_node_path: &str,
+		time: Time,
+	) -> Option<(ViewStateFlags, Vec<(Posef, Fovf)>)> {
+		self.pose_cache()
+			.and_then(|cache| cache.locate_views(view_configuration_type, base_node_path, time))
+			.or_else(|| {
+				lock(&self.pose_history).locate_views(view_configuration_type, base_node_path, time)
+			})
+	}
+	/// Apply a state the Stardust server wants this session to be in, ignoring invalid transitions.
+	pub fn server_state_changed(&self, target: SessionState) {
+		let mut lifecycle = lock(&self.lifecycle);
+		let valid = match target {
+			SessionState::READY => lifecycle.state == SessionState::IDLE,
+			SessionState::SYNCHRONIZED | SessionState::VISIBLE | SessionState::FOCUSED => {
+				lifecycle.running
+					&& (lifecycle.state == SessionState::READY
+						|| running_index(lifecycle.state).is_some())
+			}
+			SessionState::STOPPING => {
+				lifecycle.running && lifecycle.state != SessionState::STOPPING
+			}
+			SessionState::EXITING | SessionState::LOSS_PENDING => {
+				lifecycle.state != SessionState::EXITING
+					&& lifecycle.state != SessionState::LOSS_PENDING
+			}
+			_ => false,
+		};
+		if !valid {
+			eprintln!(
+				"Stardust server requested invalid session state transition {:?} -> {:?}",
+				lifecycle.state, target
+			);
+			return;
+		}
+
+		match target {
+			SessionState::SYNCHRONIZED | SessionState::VISIBLE | SessionState::FOCUSED => {
+				lifecycle.step_running_state(target)
+			}
+			SessionState::STOPPING => lifecycle.stop(),
+			// a running session has to be ended by the app before it can exit
+			SessionState::EXITING if lifecycle.running => {
+				lifecycle.exit_requested = true;
+				lifecycle.stop();
+			}
+			_ => lifecycle.set_state(target),
+		}
+	}
+
+	/// The app's frame loop is now in sync with the server's, so a session that's been begun is synchronized.
+	pub fn frame_synchronized(&self) {
+		let mut lifecycle = lock(&self.lifecycle);
+		if lifecycle.running && lifecycle.state == SessionState::READY {
+			lifecycle.set_state(SessionState::SYNCHRONIZED);
+		}
+	}
+
+	/// Checks the session can run before asking the server anything, then applies it once the server knows.
+	///
+	/// The lifecycle isn't locked across the server calls, so another thread can move the session along in between and has to be checked for again.
+	fn begin(&self, view_configuration_type: ViewConfigurationType) -> Result<(), XrResult> {
+		let check = |lifecycle: &SessionLifecycle| {
+			if lifecycle.running {
+				return Err(XrResult::ERROR_SESSION_RUNNING);
+			}
+			if lifecycle.state != SessionState::READY {
+				return Err(XrResult::ERROR_SESSION_NOT_READY);
+			}
+			Ok(())
+		};
+		check(&lock(&self.lifecycle))?;
+		view_count(view_configuration_type)?;
+		let instance = self.instance()?;
+		let blend_modes = environment_blend_modes(&instance, self.system, view_configuration_type)?;
+
+		let mut lifecycle = lock(&self.lifecycle);
+		check(&lifecycle)?;
+		instance.send_signal(
+			&self.node_path,
+			"begin",
+			&view_configuration_type.into_raw(),
+		)?;
+		lifecycle.running = true;
+		lifecycle.view_configuration_type = view_configuration_type;
+		lifecycle.environment_blend_modes = blend_modes;
+		Ok(())
+	}
+	fn end(&self) -> Result<(), XrResult> {
+		let instance = self.instance()?;
+		let mut lifecycle = lock(&self.lifecycle);
+		if !lifecycle.running {
+			return Err(XrResult::ERROR_SESSION_NOT_RUNNING);
+		}
+		if lifecycle.state != SessionState::STOPPING {
+			return Err(XrResult::ERROR_SESSION_NOT_STOPPING);
+		}
+		instance.send_signal(&self.node_path, "end", &())?;
+		lifecycle.running = false;
+		drop(lifecycle);
+
+		// still STOPPING, so nothing can begin again before the last run's frames and poses are gone
+		self.frame_loop().reset();
+		self.set_pose_cache(None);
+		*lock(&self.pose_history) = PoseHistory::default();
+
+		let mut lifecycle = lock(&self.lifecycle);
+		lifecycle.set_state(SessionState::IDLE);
+		if lifecycle.exit_requested {
+			lifecycle.set_state(SessionState::EXITING);
+		}
+		Ok(())
+	}
+	fn request_exit(&self) -> Result<(), XrResult> {
+		let instance = self.instance()?;
+		let mut lifecycle = lock(&self.lifecycle);
+		if !lifecycle.running {
+			return Err(XrResult::ERROR_SESSION_NOT_RUNNING);
+		}
+		instance.send_signal(&self.node_path, "request_exit", &())?;
+		lifecycle.exit_requested = true;
+		lifecycle.stop();
+		Ok(())
+	}
 }
 
 /// # Safety
@@ -54,14 +383,13 @@ pub unsafe extern "system" fn xrCreateSession(
 ) -> XrResult {
 	wrap_oxr! {
 		let instance = oxr_instance.get_stardust()?;
-		let next_chain = get_next_chain(create_info);
-		let contains_graphics = next_chain.iter().any(|next| format!("{:?}", next.ty).contains("_enable")); // another ugly hack
-		if !contains_graphics && !instance.extension_headless_enabled {
-			Err(XrResult::ERROR_GRAPHICS_REQUIREMENTS_CALL_MISSING)?;
-		}
+		let graphics = GraphicsBinding::from_next_chain(&instance, create_info.next)?;
 
-		let stardust_session = Box::new(StardustSession::new(oxr_instance, create_info.system_id)?);
-		*session = Session::from_raw(Box::into_raw(stardust_session) as u64);
+		let stardust_session = StardustSession::new(oxr_instance, create_info.system_id, graphics)?;
+		let node_path = stardust_session.node_path.clone();
+		*session = Handle::create_child(stardust_session, &oxr_instance)?;
+		session.get_stardust()?.created(*session);
+		lock(&instance.sessions).insert(node_path, *session);
 	}
 }
 
@@ -70,6 +398,40 @@ pub unsafe extern "system" fn xrCreateSession(
 #[no_mangle]
 pub unsafe extern "system" fn xrDestroySession(session: Session) -> XrResult {
 	wrap_oxr! {
+		// destroying has to work even once the session's lost, to clean up after it
+		let stardust_session = session.get_stardust_even_if_lost()?;
+		let node_path = stardust_session.node_path.clone();
+		lock(&stardust_session.instance.get_stardust_even_if_lost()?.sessions).remove(&node_path);
 		session.destroy()?;
 	}
 }
+
+/// # Safety
+/// https://www.khronos.org/registry/OpenXR/specs/1.0/html/xrspec.html#xrBeginSession
+#[no_mangle]
+pub unsafe extern "system" fn xrBeginSession(
+	session: Session,
+	begin_info: &SessionBeginInfo,
+) -> XrResult {
+	wrap_oxr! {
+		session.get_stardust()?.begin(begin_info.primary_view_configuration_type)?;
+	}
+}
+
+/// # Safety
+/// https://www.khronos.org/registry/OpenXR/specs/1.0/html/xrspec.html#xrEndSession
+#[no_mangle]
+pub unsafe extern "system" fn xrEndSession(session: Session) -> XrResult {
+	wrap_oxr! {
+		session.get_stardust()?.end()?;
+	}
+}
+
+/// # Safety
+/// https://www.khronos.org/registry/OpenXR/specs/1.0/html/xrspec.html#xrRequestExitSession
+#[no_mangle]
+pub unsafe extern "system" fn xrRequestExitSession(session: Session) -> XrResult {
+	wrap_oxr! {
+		session.get_stardust()?.request_exit()?;
+	}
+}
@@ -0,0 +1,228 @@
+use crate::{
+	swapchain::{SwapchainBuffers, DRM_FORMAT_ABGR8888, DRM_FORMAT_ARGB8888},
+	XrResult,
+};
+use openxr_sys::{StructureType, SwapchainCreateInfo};
+use std::{
+	ffi::c_void,
+	fs::File,
+	os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd},
+	ptr,
+};
+
+/// Raw structure type of [`SwapchainImageMemoryStardust`], in the range of an extension number we've not registered yet.
+pub const SWAPCHAIN_IMAGE_MEMORY_STARDUST: i32 = 1_000_900_000;
+
+/// Swapchain formats in order of preference, the DRM fourcc of the linear pixel layout.
+///
+/// There's nothing to tell sRGB from linear data on the CPU, so the server treats them as sRGB like any other app would write.
+const SWAPCHAIN_FORMATS: [u32; 2] = [DRM_FORMAT_ABGR8888, DRM_FORMAT_ARGB8888];
+pub fn swapchain_formats() -> Vec<i64> {
+	SWAPCHAIN_FORMATS.iter().map(|f| *f as i64).collect()
+}
+
+/// Rows are padded out to this many bytes, which keeps GPU importers happy on the server side.
+const ROW_ALIGNMENT: u32 = 256;
+
+/// What `xrEnumerateSwapchainImages` fills in for swapchains without a graphics API, such as under XR_MND_headless.
+///
+/// Each image is `array_size * face_count` layers of `height` rows of `row_pitch` bytes, one after the other.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SwapchainImageMemoryStardust {
+	pub ty: StructureType,
+	pub next: *mut c_void,
+	/// Mapped into the app for as long as the swapchain lives
+	pub data: *mut c_void,
+	pub size: u64,
+	pub row_pitch: u32,
+	/// The memfd behind `data`, in case the app wants to share it further
+	pub fd: i32,
+}
+
+struct MemoryImage {
+	fd: OwnedFd,
+	data: *mut c_void,
+}
+
+/// Swapchain images in memfd-backed shared memory, needing no GPU at all.
+pub struct MemorySwapchainImages {
+	images: Vec<MemoryImage>,
+	row_pitch: u32,
+	size: usize,
+}
+// the mappings are shared memory the app writes through, we only hand out the pointers and unmap them
+unsafe impl Send for MemorySwapchainImages {}
+unsafe impl Sync for MemorySwapchainImages {}
+impl MemorySwapchainImages {
+	pub fn new(
+		create_info: &SwapchainCreateInfo,
+		image_count: u32,
+	) -> Result<(Self, SwapchainBuffers), XrResult> {
+		let fourcc = SWAPCHAIN_FORMATS
+			.into_iter()
+			.find(|f| *f as i64 == create_info.format)
+			.ok_or(XrResult::ERROR_SWAPCHAIN_FORMAT_UNSUPPORTED)?;
+		if create_info.sample_count != 1 || create_info.mip_count != 1 {
+			// nothing on the CPU side would resolve or generate these
+			return Err(XrResult::ERROR_FEATURE_UNSUPPORTED);
+		}
+		let row_pitch = (create_info.width * 4).next_multiple_of(ROW_ALIGNMENT);
+		let layer_size = row_pitch as usize * create_info.height as usize;
+		let size = layer_size * (create_info.array_size * create_info.face_count) as usize;
+
+		// anything that fails partway through is cleaned up by dropping this
+		let mut swapchain_images = MemorySwapchainImages {
+			images: Vec::new(),
+			row_pitch,
+			size,
+		};
+		for _ in 0..image_count {
+			let fd = unsafe {
+				libc::memfd_create(c"openxr-stardust-swapchain".as_ptr(), libc::MFD_CLOEXEC)
+			};
+			if fd < 0 {
+				return Err(XrResult::ERROR_OUT_OF_MEMORY);
+			}
+			let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+			File::from(
+				fd.try_clone()
+					.map_err(|_| XrResult::ERROR_RUNTIME_FAILURE)?,
+			)
+			.set_len(size as u64)
+			.map_err(|_| XrResult::ERROR_OUT_OF_MEMORY)?;
+			let data = unsafe {
+				libc::mmap(
+					ptr::null_mut(),
+					size,
+					libc::PROT_READ | libc::PROT_WRITE,
+					libc::MAP_SHARED,
+					fd.as_raw_fd(),
+					0,
+				)
+			};
+			if data == libc::MAP_FAILED {
+				return Err(XrResult::ERROR_OUT_OF_MEMORY);
+			}
+			swapchain_images.images.push(MemoryImage { fd, data });
+		}
+
+		let buffers = SwapchainBuffers::Memory {
+			fourcc,
+			row_pitch,
+			layer_size: layer_size as u64,
+		};
+		Ok((swapchain_images, buffers))
+	}
+
+	/// Where image `index` is mapped, for `row_pitch` byte rows.
+	pub fn data(&self, index: usize) -> *mut u8 {
+		self.images[index].data as *mut u8
+	}
+	pub fn row_pitch(&self) -> u32 {
+		self.row_pitch
+	}
+
+	pub fn xr_images(&self) -> Vec<SwapchainImageMemoryStardust> {
+		self.images
+			.iter()
+			.map(|image| SwapchainImageMemoryStardust {
+				ty: StructureType::from_raw(SWAPCHAIN_IMAGE_MEMORY_STARDUST),
+				next: ptr::null_mut(),
+				data: image.data,
+				size: self.size as u64,
+				row_pitch: self.row_pitch,
+				fd: image.fd.as_raw_fd(),
+			})
+			.collect()
+	}
+	/// The memfd behind each image, in order.
+	pub fn fds(&self) -> Vec<BorrowedFd<'_>> {
+		self.images.iter().map(|image| image.fd.as_fd()).collect()
+	}
+}
+impl Drop for MemorySwapchainImages {
+	fn drop(&mut self) {
+		for image in self.images.drain(..) {
+			unsafe { libc::munmap(image.data, self.size) };
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::swapchain::tests::create_info;
+	use std::{io::Read, slice};
+
+	#[test]
+	fn pixels_written_through_the_mapping_are_in_the_fd() {
+		let (width, height) = (3, 2);
+		let (images, buffers) =
+			MemorySwapchainImages::new(&create_info(DRM_FORMAT_ABGR8888, width, height), 2)
+				.unwrap();
+		let SwapchainBuffers::Memory {
+			fourcc,
+			row_pitch,
+			layer_size,
+		} = buffers
+		else {
+			panic!("not shared memory: {buffers:?}");
+		};
+		assert_eq!(fourcc, DRM_FORMAT_ABGR8888);
+		assert_eq!(row_pitch, ROW_ALIGNMENT);
+		assert_eq!(layer_size, (row_pitch * height) as u64);
+
+		let xr_images = images.xr_images();
+		assert_eq!(xr_images.len(), 2);
+		let image = xr_images[1];
+		assert_eq!(
+			image.ty,
+			StructureType::from_raw(SWAPCHAIN_IMAGE_MEMORY_STARDUST)
+		);
+		let fds = images.fds();
+		assert_eq!(fds.len(), 2);
+		assert_eq!(image.fd, fds[1].as_raw_fd());
+		assert_eq!(image.row_pitch, row_pitch);
+		let data = unsafe { slice::from_raw_parts_mut(image.data as *mut u8, image.size as usize) };
+		for y in 0..height as usize {
+			for x in 0..width as usize {
+				let pixel = &mut data[y * row_pitch as usize + x * 4..][..4];
+				pixel.copy_from_slice(&[x as u8, y as u8, 0x80, 0xFF]);
+			}
+		}
+
+		let mut contents = Vec::new();
+		File::from(fds[1].try_clone_to_owned().unwrap())
+			.read_to_end(&mut contents)
+			.unwrap();
+		assert_eq!(contents.len(), image.size as usize);
+		for y in 0..height as usize {
+			for x in 0..width as usize {
+				assert_eq!(
+					contents[y * row_pitch as usize + x * 4..][..4],
+					[x as u8, y as u8, 0x80, 0xFF]
+				);
+			}
+		}
+		// the other image is its own memory
+		let other = unsafe {
+			slice::from_raw_parts(xr_images[0].data as *const u8, xr_images[0].size as usize)
+		};
+		assert!(other.iter().all(|byte| *byte == 0));
+	}
+
+	#[test]
+	fn only_8_bit_rgba_formats() {
+		for format in [DRM_FORMAT_ABGR8888, DRM_FORMAT_ARGB8888] {
+			assert!(MemorySwapchainImages::new(&create_info(format, 4, 4), 1).is_ok());
+		}
+		// XRGB8888, and a GL and a Vulkan format apps might try out of habit
+		for format in [u32::from_le_bytes(*b"XR24"), 0x8C43, 43] {
+			assert!(matches!(
+				MemorySwapchainImages::new(&create_info(format, 4, 4), 1),
+				Err(XrResult::ERROR_SWAPCHAIN_FORMAT_UNSUPPORTED)
+			));
+		}
+	}
+}
@@ -0,0 +1,324 @@
+use crate::{
+	handle::{Handle, HandleType, Handles, Node},
+	pose_cache::validate_time,
+	session::StardustSession,
+	system::view_count,
+	util::{delay_from_time, enumerate, find_in_next_chain_mut, StardustFov, StardustPose},
+	XrResult,
+};
+use openxr_sys::{
+	Posef, ReferenceSpaceCreateInfo, ReferenceSpaceType, Session, Space, SpaceLocation,
+	SpaceLocationFlags, SpaceVelocity, SpaceVelocityFlags, StructureType, Time, Vector3f, View,
+	ViewLocateInfo, ViewState, ViewStateFlags,
+};
+use serde::Deserialize;
+use slotmap::{DefaultKey, SecondaryMap};
+use std::{ptr, sync::Arc};
+
+impl Handle for Space {
+	type StardustType = StardustSpace;
+	const TYPE: HandleType = HandleType::Space;
+
+	fn raw(&self) -> u64 {
+		self.into_raw()
+	}
+	fn from_raw(raw: u64) -> Self {
+		Space::from_raw(raw)
+	}
+	fn table(handles: &mut Handles) -> &mut SecondaryMap<DefaultKey, Arc<StardustSpace>> {
+		&mut handles.spaces
+	}
+	fn check_lost(stardust: &StardustSpace) -> Result<(), XrResult> {
+		stardust.session().map(drop)
+	}
+}
+impl Node for StardustSpace {
+	fn server_node_path(&self) -> Option<&str> {
+		Some(&self.node_path)
+	}
+}
+
+/// The reference spaces we know how to map onto Stardust spatials.
+const REFERENCE_SPACE_TYPES: [ReferenceSpaceType; 3] = [
+	ReferenceSpaceType::VIEW,
+	ReferenceSpaceType::LOCAL,
+	ReferenceSpaceType::STAGE,
+];
+
+pub fn pose_valid(pose: &Posef) -> bool {
+	let q = pose.orientation;
+	let length_squared = q.x * q.x + q.y * q.y + q.z * q.z + q.w * q.w;
+	(length_squared - 1.0).abs() < 0.01
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StardustSpaceLocation {
+	pub pose: StardustPose,
+	position_valid: bool,
+	orientation_valid: bool,
+	position_tracked: bool,
+	orientation_tracked: bool,
+}
+impl StardustSpaceLocation {
+	pub fn flags(&self) -> SpaceLocationFlags {
+		let mut flags = SpaceLocationFlags::EMPTY;
+		if self.orientation_valid {
+			flags |= SpaceLocationFlags::ORIENTATION_VALID;
+		}
+		if self.position_valid {
+			flags |= SpaceLocationFlags::POSITION_VALID;
+		}
+		if self.orientation_tracked {
+			flags |= SpaceLocationFlags::ORIENTATION_TRACKED;
+		}
+		if self.position_tracked {
+			flags |= SpaceLocationFlags::POSITION_TRACKED;
+		}
+		flags
+	}
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StardustViewLocation {
+	pub pose: StardustPose,
+	pub fov: StardustFov,
+}
+#[derive(Debug, Deserialize)]
+pub struct StardustViewLocations {
+	pub views: Vec<StardustViewLocation>,
+	position_valid: bool,
+	orientation_valid: bool,
+	position_tracked: bool,
+	orientation_tracked: bool,
+}
+impl StardustViewLocations {
+	pub fn flags(&self) -> ViewStateFlags {
+		let mut flags = ViewStateFlags::EMPTY;
+		if self.orientation_valid {
+			flags |= ViewStateFlags::ORIENTATION_VALID;
+		}
+		if self.position_valid {
+			flags |= ViewStateFlags::POSITION_VALID;
+		}
+		if self.orientation_tracked {
+			flags |= ViewStateFlags::ORIENTATION_TRACKED;
+		}
+		if self.position_tracked {
+			flags |= ViewStateFlags::POSITION_TRACKED;
+		}
+		flags
+	}
+}
+
+pub struct StardustSpace {
+	session: Session,
+	node_path: String,
+	reference_space_type: ReferenceSpaceType,
+}
+impl StardustSpace {
+	fn new_reference(
+		session: Session,
+		create_info: &ReferenceSpaceCreateInfo,
+	) -> Result<Self, XrResult> {
+		let stardust_session = session.get_stardust()?;
+		if !reference_space_types(&stardust_session)?.contains(&create_info.reference_space_type) {
+			return Err(XrResult::ERROR_REFERENCE_SPACE_UNSUPPORTED);
+		}
+		if !pose_valid(&create_info.pose_in_reference_space) {
+			return Err(XrResult::ERROR_POSE_INVALID);
+		}
+
+		let id = nanoid::nanoid!();
+		let session_node_path = stardust_session.node_path().to_string();
+		stardust_session.instance()?.send_signal(
+			&session_node_path,
+			"create_reference_space",
+			&(
+				&id,
+				create_info.reference_space_type.into_raw(),
+				StardustPose::from(create_info.pose_in_reference_space),
+			),
+		)?;
+
+		Ok(StardustSpace {
+			session,
+			node_path: format!("{}/{}", session_node_path, id),
+			reference_space_type: create_info.reference_space_type,
+		})
+	}
+	pub fn session(&self) -> Result<Arc<StardustSession>, XrResult> {
+		self.session.get_stardust()
+	}
+	pub fn node_path(&self) -> &str {
+		&self.node_path
+	}
+	pub fn reference_space_type(&self) -> ReferenceSpaceType {
+		self.reference_space_type
+	}
+
+	/// Where this space is relative to the space at `base_node_path` at `time`, asking the server only if the session can't tell.
+	fn locate(
+		&self,
+		base_node_path: &str,
+		time: Time,
+	) -> Result<(SpaceLocationFlags, Posef), XrResult> {
+		let session = self.session()?;
+		if let Some(location) = session.locate_space_locally(&self.node_path, base_node_path, time)
+		{
+			return Ok(location);
+		}
+		let location: Option<StardustSpaceLocation> = session
+			.instance()?
+			.execute_method(
+				&self.node_path,
+				"locate",
+				&(base_node_path, delay_from_time(time)),
+			)?
+			.map_err(|_| XrResult::ERROR_RUNTIME_FAILURE)?;
+
+		Ok(match location {
+			Some(location) => (location.flags(), location.pose.into()),
+			None => (SpaceLocationFlags::EMPTY, Posef::IDENTITY),
+		})
+	}
+	/// How fast this space is moving relative to the space at `base_node_path` at `time`, as far as the session's recent poses can tell.
+	fn velocity(
+		&self,
+		base_node_path: &str,
+		time: Time,
+	) -> Result<(SpaceVelocityFlags, Vector3f, Vector3f), XrResult> {
+		Ok(self
+			.session()?
+			.space_velocity_locally(&self.node_path, base_node_path, time)
+			.unwrap_or_default())
+	}
+}
+
+fn reference_space_types(session: &StardustSession) -> Result<Vec<ReferenceSpaceType>, XrResult> {
+	let types: Vec<i32> = session
+		.instance()?
+		.execute_method(session.node_path(), "reference_space_types", &())?
+		.map_err(|_| XrResult::ERROR_RUNTIME_FAILURE)?;
+	Ok(types
+		.into_iter()
+		.map(ReferenceSpaceType::from_raw)
+		.filter(|t| REFERENCE_SPACE_TYPES.contains(t))
+		.collect())
+}
+
+/// # Safety
+/// https://www.khronos.org/registry/OpenXR/specs/1.0/html/xrspec.html#xrEnumerateReferenceSpaces
+#[no_mangle]
+pub unsafe extern "system" fn xrEnumerateReferenceSpaces(
+	session: Session,
+	space_capacity_input: u32,
+	space_count_output: &mut Option<u32>,
+	spaces: *mut ReferenceSpaceType,
+) -> XrResult {
+	wrap_oxr! {
+		let stardust_session = session.get_stardust()?;
+		let reference_space_types = reference_space_types(&stardust_session)?;
+		enumerate(space_capacity_input, space_count_output, spaces, &reference_space_types)?;
+	}
+}
+
+/// # Safety
+/// https://www.khronos.org/registry/OpenXR/specs/1.0/html/xrspec.html#xrCreateReferenceSpace
+#[no_mangle]
+pub unsafe extern "system" fn xrCreateReferenceSpace(
+	session: Session,
+	create_info: &ReferenceSpaceCreateInfo,
+	space: &mut Space,
+) -> XrResult {
+	wrap_oxr! {
+		*space = Handle::create_child(StardustSpace::new_reference(session, create_info)?, &session)?;
+	}
+}
+
+/// # Safety
+/// https://www.khronos.org/registry/OpenXR/specs/1.0/html/xrspec.html#xrDestroySpace
+#[no_mangle]
+pub unsafe extern "system" fn xrDestroySpace(space: Space) -> XrResult {
+	wrap_oxr! {
+		space.destroy()?;
+	}
+}
+
+/// # Safety
+/// https://www.khronos.org/registry/OpenXR/specs/1.0/html/xrspec.html#xrLocateSpace
+#[no_mangle]
+pub unsafe extern "system" fn xrLocateSpace(
+	space: Space,
+	base_space: Space,
+	time: Time,
+	location: &mut SpaceLocation,
+) -> XrResult {
+	wrap_oxr! {
+		validate_time(time)?;
+		let base_node_path = base_space.get_stardust()?.node_path().to_string();
+		let stardust_space = space.get_stardust()?;
+		let (location_flags, pose) = stardust_space.locate(&base_node_path, time)?;
+		location.location_flags = location_flags;
+		location.pose = pose;
+
+		if let Some(velocity) = find_in_next_chain_mut::<SpaceVelocity>(location.next, StructureType::SPACE_VELOCITY) {
+			let (velocity_flags, linear_velocity, angular_velocity) = stardust_space.velocity(&base_node_path, time)?;
+			velocity.velocity_flags = velocity_flags;
+			velocity.linear_velocity = linear_velocity;
+			velocity.angular_velocity = angular_velocity;
+		}
+	}
+}
+
+/// # Safety
+/// https://www.khronos.org/registry/OpenXR/specs/1.0/html/xrspec.html#xrLocateViews
+#[no_mangle]
+pub unsafe extern "system" fn xrLocateViews(
+	session: Session,
+	view_locate_info: &ViewLocateInfo,
+	view_state: &mut ViewState,
+	view_capacity_input: u32,
+	view_count_output: &mut Option<u32>,
+	views_ptr: *mut View,
+) -> XrResult {
+	wrap_oxr! {
+		let view_count = view_count(view_locate_info.view_configuration_type)?;
+		validate_time(view_locate_info.display_time)?;
+		let space_node_path = view_locate_info.space.get_stardust()?.node_path().to_string();
+		let stardust_session = session.get_stardust()?;
+		let local = stardust_session.locate_views_locally(view_locate_info.view_configuration_type, &space_node_path, view_locate_info.display_time);
+		if let Some((flags, locations)) = local.filter(|(_, views)| views.len() == view_count) {
+			view_state.view_state_flags = flags;
+			let views = locations.into_iter().map(|(pose, fov)| View {
+				ty: StructureType::VIEW,
+				next: ptr::null_mut(),
+				pose,
+				fov,
+			}).collect::<Vec<_>>();
+			enumerate(view_capacity_input, view_count_output, views_ptr, &views)?;
+			return Ok(());
+		}
+		let system_node_path = stardust_session.system_node_path();
+		let locations: StardustViewLocations = stardust_session.instance()?.execute_method(
+			&system_node_path,
+			"locate_views",
+			&(
+				view_locate_info.view_configuration_type.into_raw(),
+				space_node_path,
+				delay_from_time(view_locate_info.display_time),
+			),
+		)?.map_err(|_| XrResult::ERROR_RUNTIME_FAILURE)?;
+		if locations.views.len() != view_count {
+			Err(XrResult::ERROR_RUNTIME_FAILURE)?;
+		}
+
+		view_state.view_state_flags = locations.flags();
+		let views = locations.views.into_iter().map(|v| View {
+			ty: StructureType::VIEW,
+			next: ptr::null_mut(),
+			pose: v.pose.into(),
+			fov: v.fov.into(),
+		}).collect::<Vec<_>>();
+		enumerate(view_capacity_input, view_count_output, views_ptr, &views)?;
+	}
+}
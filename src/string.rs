@@ -2,8 +2,9 @@ use openxr_sys::Path;
 use slotmap::Key;
 
 use crate::{
+	handle::Handle,
 	oxr::{Instance, StructureType, MAX_RESULT_STRING_SIZE, MAX_STRUCTURE_NAME_SIZE},
-	util::{enumerate, str_from_const_char, Handle},
+	util::{enumerate, lock, str_from_const_char},
 	XrResult,
 };
 use std::ffi::c_char;
@@ -619,7 +620,7 @@ pub unsafe extern "system" fn xrStringToPath(
 	wrap_oxr! {
 		let stardust_instance = instance.get_stardust()?;
 		let path_string = str_from_const_char(path_string)?;
-		let key = stardust_instance.paths.insert(path_string.to_string());
+		let key = lock(&stardust_instance.paths).insert(path_string.to_string());
 		*path = Path::from_raw(key.data().as_ffi());
 	}
 }
@@ -0,0 +1,268 @@
+//! A stand-in for the Stardust server, so tests can go through the runtime's entry points like an app would.
+//!
+//! Every test's instance connects to the same one, which tells them apart by application name.
+
+use crate::{
+	events::xrPollEvent,
+	instance::{xrCreateInstance, xrDestroyInstance},
+	session::{xrBeginSession, xrCreateSession},
+	util::{copy_str_to_buffer, lock},
+	XrResult,
+};
+use openxr_sys::{
+	ApplicationInfo, EnvironmentBlendMode, EventDataBuffer, EventDataSessionStateChanged, Instance,
+	InstanceCreateFlags, InstanceCreateInfo, Session, SessionBeginInfo, SessionCreateFlags,
+	SessionCreateInfo, SessionState, StructureType, SystemId, Version, ViewConfigurationType,
+};
+use serde::{Deserialize, Serialize};
+use stardust_xr::{
+	messenger::{self, MessageSenderHandle},
+	scenegraph::{Scenegraph, ScenegraphError},
+	schemas::flex::{deserialize, serialize},
+};
+use std::{
+	collections::HashMap,
+	fs::{self, DirBuilder},
+	mem::zeroed,
+	os::unix::{fs::DirBuilderExt, net::UnixListener},
+	path::Path,
+	ptr,
+	sync::{Arc, Mutex, OnceLock},
+	thread,
+	time::{Duration, Instant},
+};
+
+/// Short, so stalling the server doesn't stall the tests for long.
+const METHOD_TIMEOUT: Duration = Duration::from_millis(200);
+const SYSTEM_ID: u64 = 1;
+
+/// How the server treats one test's app.
+#[derive(Default)]
+pub struct StubBehavior {
+	/// Answer `pose_snapshot` only once the method's timed out, like a stalled server.
+	pub stall_pose_snapshot: bool,
+}
+
+/// Each app's behavior by name, registered before it connects.
+fn apps() -> &'static Mutex<HashMap<String, Arc<StubBehavior>>> {
+	static APPS: OnceLock<Mutex<HashMap<String, Arc<StubBehavior>>>> = OnceLock::new();
+	APPS.get_or_init(Mutex::default)
+}
+
+#[derive(Deserialize)]
+struct SetupInfo {
+	app_info: AppInfo,
+}
+#[derive(Deserialize)]
+struct AppInfo {
+	app_name: String,
+}
+#[derive(Serialize)]
+struct SystemProperties {
+	name: String,
+	vendor_id: u32,
+	orientation_tracking: bool,
+	position_tracking: bool,
+	max_swapchain_image_width: u32,
+	max_swapchain_image_height: u32,
+	max_layer_count: u32,
+	hand_tracking: bool,
+}
+
+/// One app's connection, which only knows which app once it's been set up.
+struct Connection {
+	sender: MessageSenderHandle,
+	app: OnceLock<Arc<StubBehavior>>,
+}
+impl Scenegraph for Connection {
+	fn send_signal(&self, path: &str, method: &str, data: &[u8]) -> Result<(), ScenegraphError> {
+		let signal_error = |error: anyhow::Error| ScenegraphError::SignalError { error };
+		match method {
+			"setup_instance" => {
+				let setup: SetupInfo = deserialize(data).map_err(|e| signal_error(e.into()))?;
+				let app = lock(apps())
+					.get(&setup.app_info.app_name)
+					.cloned()
+					.ok_or(ScenegraphError::NodeNotFound)?;
+				let _ = self.app.set(app);
+			}
+			// sessions are ready to begin straight away
+			"create_session" => {
+				let id: String = deserialize(data).map_err(|e| signal_error(e.into()))?;
+				let state = serialize(SessionState::READY.into_raw()).unwrap();
+				self.sender
+					.signal(&format!("{path}/{id}"), "state_changed", &state)
+					.map_err(|e| signal_error(e.into()))?;
+			}
+			_ => (),
+		}
+		Ok(())
+	}
+	fn execute_method(
+		&self,
+		_path: &str,
+		method: &str,
+		_data: &[u8],
+	) -> Result<Vec<u8>, ScenegraphError> {
+		let app = self.app.get().ok_or(ScenegraphError::NodeNotFound)?;
+		let answer = match method {
+			"properties" => serialize(SystemProperties {
+				name: "Stub".to_string(),
+				vendor_id: 0,
+				orientation_tracking: true,
+				position_tracking: true,
+				max_swapchain_image_width: 4096,
+				max_swapchain_image_height: 4096,
+				max_layer_count: 16,
+				hand_tracking: false,
+			}),
+			"view_configurations" => {
+				serialize(vec![ViewConfigurationType::PRIMARY_STEREO.into_raw()])
+			}
+			"environment_blend_modes" => serialize(vec![EnvironmentBlendMode::OPAQUE.into_raw()]),
+			"pose_snapshot" if app.stall_pose_snapshot => {
+				// the other connections carry on while this one's stalled
+				tokio::task::block_in_place(|| thread::sleep(METHOD_TIMEOUT * 2));
+				return Err(ScenegraphError::MethodNotFound);
+			}
+			_ => return Err(ScenegraphError::MethodNotFound),
+		};
+		Ok(answer.unwrap())
+	}
+}
+
+/// Start the server and point the runtime at it, once for every test.
+fn serve() {
+	static SERVER: OnceLock<()> = OnceLock::new();
+	SERVER.get_or_init(|| {
+		// nothing's left to clean up after a test run, so clean up after the ones that have exited
+		const RUNTIME_DIR_PREFIX: &str = "openxr-stardust-test-";
+		for entry in fs::read_dir(std::env::temp_dir()).unwrap().flatten() {
+			let name = entry.file_name();
+			let pid = name
+				.to_str()
+				.and_then(|name| name.strip_prefix(RUNTIME_DIR_PREFIX));
+			if pid.is_some_and(|pid| !Path::new("/proc").join(pid).exists()) {
+				let _ = fs::remove_dir_all(entry.path());
+			}
+		}
+		let runtime_dir =
+			std::env::temp_dir().join(format!("{RUNTIME_DIR_PREFIX}{}", std::process::id()));
+		DirBuilder::new().mode(0o700).create(&runtime_dir).unwrap();
+		let listener = UnixListener::bind(runtime_dir.join("stardust-0")).unwrap();
+		listener.set_nonblocking(true).unwrap();
+		std::env::set_var("XDG_RUNTIME_DIR", &runtime_dir);
+		std::env::set_var("STARDUST_INSTANCE", "0");
+		std::env::set_var(
+			"STARDUST_OPENXR_METHOD_TIMEOUT_MS",
+			METHOD_TIMEOUT.as_millis().to_string(),
+		);
+
+		thread::spawn(move || {
+			let runtime = tokio::runtime::Builder::new_multi_thread()
+				.worker_threads(2)
+				.enable_io()
+				.build()
+				.unwrap();
+			runtime.block_on(async move {
+				let listener = tokio::net::UnixListener::from_std(listener).unwrap();
+				while let Ok((connection, _)) = listener.accept().await {
+					let (mut message_sender, mut message_receiver) = messenger::create(connection);
+					let connection = Connection {
+						sender: message_sender.handle(),
+						app: OnceLock::new(),
+					};
+					tokio::spawn(async move {
+						let _ = message_sender.flush().await;
+					});
+					tokio::spawn(async move {
+						while message_receiver.dispatch(&connection).await.is_ok() {}
+					});
+				}
+			});
+		});
+	});
+}
+
+/// An app with a headless session that's been begun, destroyed along with its instance when dropped.
+pub struct StubSession {
+	pub instance: Instance,
+	pub session: Session,
+}
+impl StubSession {
+	/// `name` has to be unique to the test, as it's how the server tells apps apart.
+	pub fn begin(name: &str, behavior: StubBehavior) -> Self {
+		serve();
+		lock(apps()).insert(name.to_string(), Arc::new(behavior));
+
+		let mut application_info = ApplicationInfo {
+			application_name: [0; 128],
+			application_version: 0,
+			engine_name: [0; 128],
+			engine_version: 0,
+			api_version: Version::new(1, 0, 0),
+		};
+		copy_str_to_buffer(name, &mut application_info.application_name);
+		let extension_names = [c"XR_MND_headless".as_ptr()];
+		let instance_create_info = InstanceCreateInfo {
+			ty: InstanceCreateInfo::TYPE,
+			next: ptr::null(),
+			create_flags: InstanceCreateFlags::EMPTY,
+			application_info,
+			enabled_api_layer_count: 0,
+			enabled_api_layer_names: ptr::null(),
+			enabled_extension_count: extension_names.len() as u32,
+			enabled_extension_names: extension_names.as_ptr(),
+		};
+		let mut instance = Instance::NULL;
+		let session_create_info = SessionCreateInfo {
+			ty: SessionCreateInfo::TYPE,
+			next: ptr::null(),
+			create_flags: SessionCreateFlags::EMPTY,
+			system_id: SystemId::from_raw(SYSTEM_ID),
+		};
+		let mut session = Session::NULL;
+		let begin_info = SessionBeginInfo {
+			ty: SessionBeginInfo::TYPE,
+			next: ptr::null(),
+			primary_view_configuration_type: ViewConfigurationType::PRIMARY_STEREO,
+		};
+		unsafe {
+			assert_eq!(
+				xrCreateInstance(&instance_create_info, &mut instance),
+				XrResult::SUCCESS
+			);
+			assert_eq!(
+				xrCreateSession(instance, &session_create_info, &mut session),
+				XrResult::SUCCESS
+			);
+			wait_for_state(instance, SessionState::READY);
+			assert_eq!(xrBeginSession(session, &begin_info), XrResult::SUCCESS);
+		}
+
+		StubSession { instance, session }
+	}
+}
+impl Drop for StubSession {
+	fn drop(&mut self) {
+		unsafe { xrDestroyInstance(self.instance) };
+	}
+}
+
+/// Poll events until the session gets to `state`.
+unsafe fn wait_for_state(instance: Instance, state: SessionState) {
+	let deadline = Instant::now() + METHOD_TIMEOUT * 10;
+	while Instant::now() < deadline {
+		let mut event: EventDataBuffer = zeroed();
+		if xrPollEvent(instance, &mut event) == XrResult::SUCCESS
+			&& event.ty == StructureType::EVENT_DATA_SESSION_STATE_CHANGED
+		{
+			let event = &*(&event as *const EventDataBuffer as *const EventDataSessionStateChanged);
+			if event.state == state {
+				return;
+			}
+		}
+		thread::sleep(Duration::from_millis(1));
+	}
+	panic!("the session never got to {state:?}");
+}
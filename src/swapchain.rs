@@ -0,0 +1,550 @@
+use crate::{
+	graphics::GraphicsBinding,
+	handle::{Handle, HandleType, Handles, Node},
+	ipc::IpcError,
+	opengl::{self, OpenGLSwapchainImages},
+	session::StardustSession,
+	shm::{
+		self, MemorySwapchainImages, SwapchainImageMemoryStardust, SWAPCHAIN_IMAGE_MEMORY_STARDUST,
+	},
+	util::{enumerate, lock},
+	vulkan::{self, VulkanSwapchainImages},
+	XrResult,
+};
+use openxr_sys::{
+	Duration, Session, Swapchain, SwapchainCreateFlags, SwapchainCreateInfo,
+	SwapchainImageAcquireInfo, SwapchainImageBaseHeader, SwapchainImageOpenGLKHR,
+	SwapchainImageReleaseInfo, SwapchainImageVulkanKHR, SwapchainImageWaitInfo,
+};
+use serde::Serialize;
+use slotmap::{DefaultKey, SecondaryMap};
+use std::{
+	collections::VecDeque,
+	os::fd::BorrowedFd,
+	sync::{Arc, Mutex},
+};
+
+impl Handle for Swapchain {
+	type StardustType = StardustSwapchain;
+	const TYPE: HandleType = HandleType::Swapchain;
+
+	fn raw(&self) -> u64 {
+		self.into_raw()
+	}
+	fn from_raw(raw: u64) -> Self {
+		Swapchain::from_raw(raw)
+	}
+	fn table(handles: &mut Handles) -> &mut SecondaryMap<DefaultKey, Arc<StardustSwapchain>> {
+		&mut handles.swapchains
+	}
+	fn check_lost(stardust: &StardustSwapchain) -> Result<(), XrResult> {
+		stardust.session().map(drop)
+	}
+}
+impl Node for StardustSwapchain {
+	fn server_node_path(&self) -> Option<&str> {
+		Some(&self.node_path)
+	}
+}
+
+/// How many images each swapchain gets, enough for the app and compositor not to wait on each other.
+pub const SWAPCHAIN_IMAGE_COUNT: u32 = 3;
+/// None of the graphics backends can export multisampled images, so apps have to resolve before releasing.
+pub const MAX_SWAPCHAIN_SAMPLE_COUNT: u32 = 1;
+
+/// `DRM_FORMAT_ABGR8888`, bytes in R, G, B, A order in memory
+pub const DRM_FORMAT_ABGR8888: u32 = u32::from_le_bytes(*b"AB24");
+/// `DRM_FORMAT_ARGB8888`, bytes in B, G, R, A order in memory
+pub const DRM_FORMAT_ARGB8888: u32 = u32::from_le_bytes(*b"AR24");
+
+/// One swapchain image exported as a single plane dmabuf.
+///
+/// The messenger can't pass file descriptors, so the dmabuf itself goes over the instance's fd channel, in the same order as the images here.
+#[derive(Debug, Serialize)]
+pub struct DmabufImage {
+	pub modifier: u64,
+	pub offset: u64,
+	pub stride: u64,
+	/// Bytes between array layers, 0 if there's only one
+	pub array_pitch: u64,
+}
+
+/// One swapchain image as an opaque Vulkan memory fd, only importable by the same driver, sent the same way as dmabufs.
+#[derive(Debug, Serialize)]
+pub struct OpaqueImage {
+	pub size: u64,
+}
+
+/// The memory backing a swapchain's images, as the server needs to import it.
+#[derive(Debug, Serialize)]
+pub enum SwapchainBuffers {
+	Dmabuf {
+		fourcc: u32,
+		srgb: bool,
+		/// GL puts the first row at the bottom
+		flip_y: bool,
+		images: Vec<DmabufImage>,
+	},
+	/// Vulkan images with no DRM modifier layout, such as depth, to recreate with the same parameters on the server's device
+	VulkanOpaque {
+		/// `VkFormat`
+		format: i32,
+		/// `VkImageUsageFlags`
+		usage: u32,
+		images: Vec<OpaqueImage>,
+	},
+	/// Linear memfd images, one fd per image sent the same way as dmabufs
+	Memory {
+		fourcc: u32,
+		row_pitch: u32,
+		layer_size: u64,
+	},
+}
+
+#[derive(Debug, Serialize)]
+struct StardustSwapchainInfo {
+	id: String,
+	width: u32,
+	height: u32,
+	array_size: u32,
+	face_count: u32,
+	mip_count: u32,
+	static_image: bool,
+	buffers: SwapchainBuffers,
+}
+
+/// The graphics API objects for a swapchain's images, which have to outlive the app's use of them.
+pub enum SwapchainImages {
+	Vulkan(Box<VulkanSwapchainImages>),
+	OpenGL(OpenGLSwapchainImages),
+	Memory(MemorySwapchainImages),
+}
+impl SwapchainImages {
+	/// The memory behind each image, in order, for the server to import.
+	fn fds(&self) -> Vec<BorrowedFd<'_>> {
+		match self {
+			SwapchainImages::Vulkan(images) => images.fds(),
+			SwapchainImages::OpenGL(images) => images.fds(),
+			SwapchainImages::Memory(images) => images.fds(),
+		}
+	}
+}
+
+/// Tracks the images the app holds as they go acquire -> wait -> release, in the order the spec requires.
+struct ImageRing {
+	image_count: u32,
+	static_image: bool,
+	next_index: u32,
+	/// Acquired and not yet released, oldest first
+	acquired: VecDeque<u32>,
+	/// Whether the oldest acquired image has been waited on
+	waited: bool,
+	/// A static swapchain's one image can only ever be acquired once
+	static_image_acquired: bool,
+	/// The image layers should show, as the app is done rendering it
+	last_released: Option<u32>,
+}
+impl ImageRing {
+	fn new(image_count: u32, static_image: bool) -> Self {
+		ImageRing {
+			image_count,
+			static_image,
+			next_index: 0,
+			acquired: VecDeque::new(),
+			waited: false,
+			static_image_acquired: false,
+			last_released: None,
+		}
+	}
+	fn acquire(&mut self) -> Result<u32, XrResult> {
+		if self.static_image_acquired || self.acquired.len() as u32 >= self.image_count {
+			return Err(XrResult::ERROR_CALL_ORDER_INVALID);
+		}
+		let index = self.next_index;
+		self.next_index = (index + 1) % self.image_count;
+		self.acquired.push_back(index);
+		self.static_image_acquired = self.static_image;
+		Ok(index)
+	}
+	/// The image the app has to wait on next, which is the oldest one acquired.
+	fn to_wait(&self) -> Result<u32, XrResult> {
+		if self.waited {
+			return Err(XrResult::ERROR_CALL_ORDER_INVALID);
+		}
+		self.acquired
+			.front()
+			.copied()
+			.ok_or(XrResult::ERROR_CALL_ORDER_INVALID)
+	}
+	fn waited(&mut self) {
+		self.waited = true;
+	}
+	fn release(&mut self) -> Result<u32, XrResult> {
+		if !self.waited {
+			return Err(XrResult::ERROR_CALL_ORDER_INVALID);
+		}
+		self.waited = false;
+		let index = self.acquired.pop_front().unwrap();
+		self.last_released = Some(index);
+		Ok(index)
+	}
+}
+
+pub struct StardustSwapchain {
+	session: Session,
+	node_path: String,
+	width: u32,
+	height: u32,
+	array_size: u32,
+	face_count: u32,
+	images: SwapchainImages,
+	ring: Mutex<ImageRing>,
+}
+impl StardustSwapchain {
+	fn new(session: Session, create_info: &SwapchainCreateInfo) -> Result<Self, XrResult> {
+		if create_info.width == 0
+			|| create_info.height == 0
+			|| create_info.array_size == 0
+			|| create_info.mip_count == 0
+			|| (create_info.face_count != 1 && create_info.face_count != 6)
+		{
+			return Err(XrResult::ERROR_VALIDATION_FAILURE);
+		}
+		if create_info
+			.create_flags
+			.contains(SwapchainCreateFlags::PROTECTED_CONTENT)
+		{
+			return Err(XrResult::ERROR_FEATURE_UNSUPPORTED);
+		}
+		let static_image = create_info
+			.create_flags
+			.contains(SwapchainCreateFlags::STATIC_IMAGE);
+		let image_count = if static_image {
+			1
+		} else {
+			SWAPCHAIN_IMAGE_COUNT
+		};
+
+		let stardust_session = session.get_stardust()?;
+		let (images, buffers) = match *stardust_session.graphics() {
+			GraphicsBinding::Headless => {
+				let (images, buffers) = MemorySwapchainImages::new(create_info, image_count)?;
+				(SwapchainImages::Memory(images), buffers)
+			}
+			GraphicsBinding::Vulkan(binding) => {
+				let (images, buffers) = VulkanSwapchainImages::new(
+					&stardust_session,
+					&binding,
+					create_info,
+					image_count,
+				)?;
+				(SwapchainImages::Vulkan(Box::new(images)), buffers)
+			}
+			GraphicsBinding::OpenGL(binding) => {
+				let (images, buffers) =
+					unsafe { OpenGLSwapchainImages::new(&binding, create_info, image_count)? };
+				(SwapchainImages::OpenGL(images), buffers)
+			}
+		};
+
+		let id = nanoid::nanoid!();
+		let session_node_path = stardust_session.node_path().to_string();
+		let instance = stardust_session.instance()?;
+		// before the signal, so the server's never told about a swapchain it can't get the memory for
+		instance.send_fds(&id, &images.fds())?;
+		instance.send_signal(
+			&session_node_path,
+			"create_swapchain",
+			&StardustSwapchainInfo {
+				id: id.clone(),
+				width: create_info.width,
+				height: create_info.height,
+				array_size: create_info.array_size,
+				face_count: create_info.face_count,
+				mip_count: create_info.mip_count,
+				static_image,
+				buffers,
+			},
+		)?;
+
+		Ok(StardustSwapchain {
+			session,
+			node_path: format!("{}/{}", session_node_path, id),
+			width: create_info.width,
+			height: create_info.height,
+			array_size: create_info.array_size,
+			face_count: create_info.face_count,
+			images,
+			ring: Mutex::new(ImageRing::new(image_count, static_image)),
+		})
+	}
+	pub fn session(&self) -> Result<Arc<StardustSession>, XrResult> {
+		self.session.get_stardust()
+	}
+	pub fn node_path(&self) -> &str {
+		&self.node_path
+	}
+	pub fn width(&self) -> u32 {
+		self.width
+	}
+	pub fn height(&self) -> u32 {
+		self.height
+	}
+	pub fn array_size(&self) -> u32 {
+		self.array_size
+	}
+	pub fn face_count(&self) -> u32 {
+		self.face_count
+	}
+	/// The most recently released image, which is what the compositor should show.
+	pub fn released_image(&self) -> Option<u32> {
+		lock(&self.ring).last_released
+	}
+}
+
+/// # Safety
+/// https://www.khronos.org/registry/OpenXR/specs/1.0/html/xrspec.html#xrEnumerateSwapchainFormats
+#[no_mangle]
+pub unsafe extern "system" fn xrEnumerateSwapchainFormats(
+	session: Session,
+	format_capacity_input: u32,
+	format_count_output: &mut Option<u32>,
+	formats: *mut i64,
+) -> XrResult {
+	wrap_oxr! {
+		let swapchain_formats = match session.get_stardust()?.graphics() {
+			GraphicsBinding::Headless => shm::swapchain_formats(),
+			GraphicsBinding::Vulkan(_) => vulkan::swapchain_formats(),
+			GraphicsBinding::OpenGL(_) => opengl::swapchain_formats(),
+		};
+		enumerate(format_capacity_input, format_count_output, formats, &swapchain_formats)?;
+	}
+}
+
+/// # Safety
+/// https://www.khronos.org/registry/OpenXR/specs/1.0/html/xrspec.html#xrCreateSwapchain
+#[no_mangle]
+pub unsafe extern "system" fn xrCreateSwapchain(
+	session: Session,
+	create_info: &SwapchainCreateInfo,
+	swapchain: &mut Swapchain,
+) -> XrResult {
+	wrap_oxr! {
+		*swapchain = Handle::create_child(StardustSwapchain::new(session, create_info)?, &session)?;
+	}
+}
+
+/// # Safety
+/// https://www.khronos.org/registry/OpenXR/specs/1.0/html/xrspec.html#xrDestroySwapchain
+#[no_mangle]
+pub unsafe extern "system" fn xrDestroySwapchain(swapchain: Swapchain) -> XrResult {
+	wrap_oxr! {
+		swapchain.destroy()?;
+	}
+}
+
+/// # Safety
+/// https://www.khronos.org/registry/OpenXR/specs/1.0/html/xrspec.html#xrEnumerateSwapchainImages
+#[no_mangle]
+pub unsafe extern "system" fn xrEnumerateSwapchainImages(
+	swapchain: Swapchain,
+	image_capacity_input: u32,
+	image_count_output: &mut Option<u32>,
+	images: *mut SwapchainImageBaseHeader,
+) -> XrResult {
+	wrap_oxr! {
+		match &swapchain.get_stardust()?.images {
+			SwapchainImages::Vulkan(vulkan_images) => {
+				if !images.is_null() && image_capacity_input > 0 && (*images).ty != SwapchainImageVulkanKHR::TYPE {
+					Err(XrResult::ERROR_VALIDATION_FAILURE)?;
+				}
+				enumerate(image_capacity_input, image_count_output, images as *mut SwapchainImageVulkanKHR, &vulkan_images.xr_images())?;
+			}
+			SwapchainImages::OpenGL(opengl_images) => {
+				if !images.is_null() && image_capacity_input > 0 && (*images).ty != SwapchainImageOpenGLKHR::TYPE {
+					Err(XrResult::ERROR_VALIDATION_FAILURE)?;
+				}
+				enumerate(image_capacity_input, image_count_output, images as *mut SwapchainImageOpenGLKHR, &opengl_images.xr_images())?;
+			}
+			SwapchainImages::Memory(memory_images) => {
+				if !images.is_null() && image_capacity_input > 0 && (*images).ty.into_raw() != SWAPCHAIN_IMAGE_MEMORY_STARDUST {
+					Err(XrResult::ERROR_VALIDATION_FAILURE)?;
+				}
+				enumerate(image_capacity_input, image_count_output, images as *mut SwapchainImageMemoryStardust, &memory_images.xr_images())?;
+			}
+		}
+	}
+}
+
+/// # Safety
+/// https://www.khronos.org/registry/OpenXR/specs/1.0/html/xrspec.html#xrAcquireSwapchainImage
+#[no_mangle]
+pub unsafe extern "system" fn xrAcquireSwapchainImage(
+	swapchain: Swapchain,
+	_acquire_info: Option<&SwapchainImageAcquireInfo>,
+	index: &mut u32,
+) -> XrResult {
+	wrap_oxr! {
+		*index = lock(&swapchain.get_stardust()?.ring).acquire()?;
+	}
+}
+
+/// # Safety
+/// https://www.khronos.org/registry/OpenXR/specs/1.0/html/xrspec.html#xrWaitSwapchainImage
+#[no_mangle]
+pub unsafe extern "system" fn xrWaitSwapchainImage(
+	swapchain: Swapchain,
+	wait_info: &SwapchainImageWaitInfo,
+) -> XrResult {
+	wrap_oxr! {
+		let stardust_swapchain = swapchain.get_stardust()?;
+		// the spec has the app synchronize waits and releases on a swapchain, so nothing moves the ring on while we wait
+		let index = lock(&stardust_swapchain.ring).to_wait()?;
+		// seconds, with None waiting until the compositor is done with the image no matter how long it takes
+		let timeout = (wait_info.timeout != Duration::INFINITE).then(|| wait_info.timeout.as_nanos().max(0) as f64 / 1e9);
+		let instance = stardust_swapchain.session()?.instance()?;
+		// the server answers once the app's timeout is up, so give it that long on top of the usual round trip
+		let method_timeout = timeout.map(|timeout| instance.method_timeout() + std::time::Duration::from_secs_f64(timeout));
+		let ready: bool = match instance.execute_method_with_timeout(&stardust_swapchain.node_path, "wait_image", &(index, timeout), method_timeout) {
+			// still the app's timeout running out, just with the answer held up on the way back
+			Err(IpcError::TimedOut) => false,
+			answer => answer?.map_err(|_| XrResult::ERROR_RUNTIME_FAILURE)?,
+		};
+		if !ready {
+			// a success code, so the app can just wait again
+			Err(XrResult::TIMEOUT_EXPIRED)?;
+		}
+		lock(&stardust_swapchain.ring).waited();
+	}
+}
+
+/// # Safety
+/// https://www.khronos.org/registry/OpenXR/specs/1.0/html/xrspec.html#xrReleaseSwapchainImage
+#[no_mangle]
+pub unsafe extern "system" fn xrReleaseSwapchainImage(
+	swapchain: Swapchain,
+	_release_info: Option<&SwapchainImageReleaseInfo>,
+) -> XrResult {
+	wrap_oxr! {
+		let stardust_swapchain = swapchain.get_stardust()?;
+		let index = lock(&stardust_swapchain.ring).release()?;
+		if let SwapchainImages::OpenGL(opengl_images) = &stardust_swapchain.images {
+			opengl_images.released(index);
+		}
+		stardust_swapchain.session()?.instance()?.send_signal(&stardust_swapchain.node_path, "release", &index)?;
+	}
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+	use super::*;
+	use openxr_sys::SwapchainUsageFlags;
+	use std::ptr;
+
+	/// A single plain color image, for the backends' tests to make swapchains from.
+	pub(crate) fn create_info(format: u32, width: u32, height: u32) -> SwapchainCreateInfo {
+		SwapchainCreateInfo {
+			ty: SwapchainCreateInfo::TYPE,
+			next: ptr::null(),
+			create_flags: SwapchainCreateFlags::EMPTY,
+			usage_flags: SwapchainUsageFlags::COLOR_ATTACHMENT,
+			format: format as i64,
+			sample_count: 1,
+			width,
+			height,
+			face_count: 1,
+			array_size: 1,
+			mip_count: 1,
+		}
+	}
+
+	/// Acquire, wait and release the next image, returning its index.
+	fn cycle(ring: &mut ImageRing) -> u32 {
+		let index = ring.acquire().unwrap();
+		assert_eq!(ring.to_wait(), Ok(index));
+		ring.waited();
+		assert_eq!(ring.release(), Ok(index));
+		index
+	}
+
+	#[test]
+	fn images_wrap_around() {
+		let mut ring = ImageRing::new(3, false);
+		let indices: Vec<u32> = (0..7).map(|_| cycle(&mut ring)).collect();
+		assert_eq!(indices, [0, 1, 2, 0, 1, 2, 0]);
+		assert_eq!(ring.last_released, Some(0));
+	}
+
+	#[test]
+	fn acquired_ahead_are_waited_and_released_oldest_first() {
+		let mut ring = ImageRing::new(3, false);
+		assert_eq!(ring.acquire(), Ok(0));
+		assert_eq!(ring.acquire(), Ok(1));
+		assert_eq!(ring.to_wait(), Ok(0));
+		ring.waited();
+		// acquiring more while one's waited on doesn't change what's released
+		assert_eq!(ring.acquire(), Ok(2));
+		assert_eq!(ring.release(), Ok(0));
+		assert_eq!(ring.to_wait(), Ok(1));
+		ring.waited();
+		assert_eq!(ring.release(), Ok(1));
+		assert_eq!(ring.to_wait(), Ok(2));
+		ring.waited();
+		assert_eq!(ring.release(), Ok(2));
+		assert_eq!(ring.last_released, Some(2));
+	}
+
+	#[test]
+	fn cant_acquire_more_than_there_are() {
+		let mut ring = ImageRing::new(3, false);
+		for index in 0..3 {
+			assert_eq!(ring.acquire(), Ok(index));
+		}
+		assert_eq!(ring.acquire(), Err(XrResult::ERROR_CALL_ORDER_INVALID));
+		ring.to_wait().unwrap();
+		ring.waited();
+		ring.release().unwrap();
+		assert_eq!(ring.acquire(), Ok(0));
+	}
+
+	#[test]
+	fn only_one_wait_outstanding() {
+		let mut ring = ImageRing::new(3, false);
+		ring.acquire().unwrap();
+		ring.acquire().unwrap();
+		assert_eq!(ring.to_wait(), Ok(0));
+		ring.waited();
+		// the second image can't be waited on until the first is released
+		assert_eq!(ring.to_wait(), Err(XrResult::ERROR_CALL_ORDER_INVALID));
+	}
+
+	#[test]
+	fn wait_needs_an_acquired_image() {
+		let mut ring = ImageRing::new(3, false);
+		assert_eq!(ring.to_wait(), Err(XrResult::ERROR_CALL_ORDER_INVALID));
+		cycle(&mut ring);
+		assert_eq!(ring.to_wait(), Err(XrResult::ERROR_CALL_ORDER_INVALID));
+	}
+
+	#[test]
+	fn release_needs_a_wait() {
+		let mut ring = ImageRing::new(3, false);
+		assert_eq!(ring.release(), Err(XrResult::ERROR_CALL_ORDER_INVALID));
+		ring.acquire().unwrap();
+		assert_eq!(ring.release(), Err(XrResult::ERROR_CALL_ORDER_INVALID));
+		assert_eq!(ring.last_released, None);
+	}
+
+	#[test]
+	fn static_image_is_only_acquired_once() {
+		let mut ring = ImageRing::new(1, true);
+		assert_eq!(cycle(&mut ring), 0);
+		assert_eq!(ring.acquire(), Err(XrResult::ERROR_CALL_ORDER_INVALID));
+		assert_eq!(ring.last_released, Some(0));
+
+		// even if it's never released
+		let mut ring = ImageRing::new(1, true);
+		assert_eq!(ring.acquire(), Ok(0));
+		assert_eq!(ring.acquire(), Err(XrResult::ERROR_CALL_ORDER_INVALID));
+	}
+}
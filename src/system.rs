@@ -1,15 +1,124 @@
 use crate::{
-	util::{copy_str_to_buffer, enumerate, Handle},
+	handle::Handle,
+	instance::StardustInstance,
+	swapchain::MAX_SWAPCHAIN_SAMPLE_COUNT,
+	util::{copy_str_to_buffer, enumerate, find_in_next_chain_mut},
 	XrResult,
 };
 use openxr_sys::{
-	EnvironmentBlendMode, Instance, StructureType, SystemGetInfo, SystemId, SystemProperties,
-	ViewConfigurationProperties, ViewConfigurationType, ViewConfigurationView,
-	MIN_COMPOSITION_LAYERS_SUPPORTED, TRUE,
+	EnvironmentBlendMode, FormFactor, Instance, StructureType, SystemGetInfo,
+	SystemHandTrackingPropertiesEXT, SystemId, SystemProperties, ViewConfigurationProperties,
+	ViewConfigurationType, ViewConfigurationView, MAX_SYSTEM_NAME_SIZE,
+	MIN_COMPOSITION_LAYERS_SUPPORTED,
 };
 use serde::Deserialize;
 use std::ptr;
 
+#[derive(Debug, Deserialize)]
+struct StardustSystemProperties {
+	name: String,
+	vendor_id: u32,
+	orientation_tracking: bool,
+	position_tracking: bool,
+	max_swapchain_image_width: u32,
+	max_swapchain_image_height: u32,
+	max_layer_count: u32,
+	hand_tracking: bool,
+}
+fn system_properties(
+	instance: &StardustInstance,
+	system_id: SystemId,
+) -> Result<StardustSystemProperties, XrResult> {
+	let mut properties: StardustSystemProperties = instance
+		.execute_method(
+			&format!("/openxr/system{}", system_id.into_raw()),
+			"properties",
+			&(),
+		)?
+		.map_err(|_| XrResult::ERROR_SYSTEM_INVALID)?;
+	// the spec won't let us take fewer than its minimum
+	properties.max_layer_count = properties
+		.max_layer_count
+		.max(MIN_COMPOSITION_LAYERS_SUPPORTED as u32);
+	Ok(properties)
+}
+/// How many layers the system can composite in one frame, as advertised in `XrSystemGraphicsProperties`.
+pub fn max_layer_count(instance: &StardustInstance, system_id: SystemId) -> Result<u32, XrResult> {
+	Ok(system_properties(instance, system_id)?.max_layer_count)
+}
+
+/// How many views a view configuration has, for the ones we support.
+pub fn view_count(view_configuration_type: ViewConfigurationType) -> Result<usize, XrResult> {
+	match view_configuration_type {
+		ViewConfigurationType::PRIMARY_MONO => Ok(1),
+		ViewConfigurationType::PRIMARY_STEREO => Ok(2),
+		_ => Err(XrResult::ERROR_VIEW_CONFIGURATION_TYPE_UNSUPPORTED),
+	}
+}
+
+/// The view configurations the server can show, out of the ones we support, in order of preference.
+pub fn view_configuration_types(
+	instance: &StardustInstance,
+	system_id: SystemId,
+) -> Result<Vec<ViewConfigurationType>, XrResult> {
+	let view_configuration_types: Vec<i32> = instance
+		.execute_method(
+			&format!("/openxr/system{}", system_id.into_raw()),
+			"view_configurations",
+			&(),
+		)?
+		.map_err(|_| XrResult::ERROR_SYSTEM_INVALID)?;
+	Ok(view_configuration_types
+		.into_iter()
+		.map(ViewConfigurationType::from_raw)
+		.filter(|view_configuration_type| view_count(*view_configuration_type).is_ok())
+		.collect())
+}
+/// Make sure both we and the server support a view configuration, returning how many views it has.
+pub fn check_view_configuration_type(
+	instance: &StardustInstance,
+	system_id: SystemId,
+	view_configuration_type: ViewConfigurationType,
+) -> Result<usize, XrResult> {
+	let view_count = view_count(view_configuration_type)?;
+	if !view_configuration_types(instance, system_id)?.contains(&view_configuration_type) {
+		return Err(XrResult::ERROR_VIEW_CONFIGURATION_TYPE_UNSUPPORTED);
+	}
+	Ok(view_count)
+}
+
+/// The blend modes the server can composite a view configuration with, in order of preference.
+///
+/// Stardust is usually passthrough, so whatever the server lists, ADDITIVE and ALPHA_BLEND come before OPAQUE.
+pub fn environment_blend_modes(
+	instance: &StardustInstance,
+	system_id: SystemId,
+	view_configuration_type: ViewConfigurationType,
+) -> Result<Vec<EnvironmentBlendMode>, XrResult> {
+	check_view_configuration_type(instance, system_id, view_configuration_type)?;
+	let blend_modes: Vec<i32> = instance
+		.execute_method(
+			&format!("/openxr/system{}", system_id.into_raw()),
+			"environment_blend_modes",
+			&view_configuration_type.into_raw(),
+		)?
+		.map_err(|_| XrResult::ERROR_SYSTEM_INVALID)?;
+	let mut supported_blend_modes: Vec<EnvironmentBlendMode> = Vec::new();
+	for mode in blend_modes.into_iter().map(EnvironmentBlendMode::from_raw) {
+		let known = [
+			EnvironmentBlendMode::OPAQUE,
+			EnvironmentBlendMode::ADDITIVE,
+			EnvironmentBlendMode::ALPHA_BLEND,
+		]
+		.contains(&mode);
+		if known && !supported_blend_modes.contains(&mode) {
+			supported_blend_modes.push(mode);
+		}
+	}
+	supported_blend_modes.sort_by_key(|mode| *mode == EnvironmentBlendMode::OPAQUE);
+	Ok(supported_blend_modes)
+}
+
 /// # Safety
 /// https://www.khronos.org/registry/OpenXR/specs/1.0/html/xrspec.html#xrGetSystem
 #[no_mangle]
@@ -18,11 +127,26 @@ pub extern "system" fn xrGetSystem(
 	get_info: &SystemGetInfo,
 	system_id: &mut SystemId,
 ) -> XrResult {
+	/// Whether the server has a system for a form factor right now.
+	#[derive(Debug, Deserialize)]
+	enum StardustSystem {
+		Available(u32),
+		/// Supported, but nothing's connected that could show it, like a headset when only a flatscreen is running
+		Unavailable,
+		Unsupported,
+	}
 	wrap_oxr! {
+		if ![FormFactor::HEAD_MOUNTED_DISPLAY, FormFactor::HANDHELD_DISPLAY].contains(&get_info.form_factor) {
+			Err(XrResult::ERROR_FORM_FACTOR_UNSUPPORTED)?;
+		}
 		let instance = instance.get_stardust()?;
 		let system = instance.execute_method("/openxr", "get_system", &(get_info.form_factor.into_raw() as u32))?;
-		let system_type: u32 = system.map_err(|_| XrResult::ERROR_FORM_FACTOR_UNSUPPORTED)?;
-		*system_id = SystemId::from_raw(system_type as u64);
+		match system.map_err(|_| XrResult::ERROR_RUNTIME_FAILURE)? {
+			StardustSystem::Available(system) => *system_id = SystemId::from_raw(system as u64),
+			// apps poll for this one until a device turns up
+			StardustSystem::Unavailable => Err(XrResult::ERROR_FORM_FACTOR_UNAVAILABLE)?,
+			StardustSystem::Unsupported => Err(XrResult::ERROR_FORM_FACTOR_UNSUPPORTED)?,
+		}
 	}
 }
 
@@ -30,19 +154,28 @@ pub extern "system" fn xrGetSystem(
 /// https://www.khronos.org/registry/OpenXR/specs/1.0/html/xrspec.html#xrGetSystemProperties
 #[no_mangle]
 pub unsafe extern "system" fn xrGetSystemProperties(
-	_instance: Instance,
+	instance: Instance,
 	system_id: SystemId,
 	properties: &mut SystemProperties,
 ) -> XrResult {
 	wrap_oxr! {
-		properties.ty = StructureType::SYSTEM_PROPERTIES;
+		let stardust_instance = instance.get_stardust()?;
+		let system = system_properties(&stardust_instance, system_id)?;
+
 		properties.system_id = system_id;
-		copy_str_to_buffer("Stardust Virtual Device", &mut properties.system_name);
-		properties.tracking_properties.orientation_tracking = TRUE;
-		properties.tracking_properties.position_tracking = TRUE;
-		properties.graphics_properties.max_layer_count = MIN_COMPOSITION_LAYERS_SUPPORTED as u32;
-		properties.graphics_properties.max_swapchain_image_width = 1024 * 16;
-		properties.graphics_properties.max_swapchain_image_height = 1024 * 16;
+		properties.vendor_id = system.vendor_id;
+		properties.system_name = [0; MAX_SYSTEM_NAME_SIZE];
+		let name_len = system.name.floor_char_boundary(MAX_SYSTEM_NAME_SIZE - 1);
+		copy_str_to_buffer(&system.name[..name_len], &mut properties.system_name);
+		properties.tracking_properties.orientation_tracking = system.orientation_tracking.into();
+		properties.tracking_properties.position_tracking = system.position_tracking.into();
+		properties.graphics_properties.max_layer_count = system.max_layer_count;
+		properties.graphics_properties.max_swapchain_image_width = system.max_swapchain_image_width;
+		properties.graphics_properties.max_swapchain_image_height = system.max_swapchain_image_height;
+
+		if let Some(hand_tracking) = find_in_next_chain_mut::<SystemHandTrackingPropertiesEXT>(properties.next, StructureType::SYSTEM_HAND_TRACKING_PROPERTIES_EXT) {
+			hand_tracking.supports_hand_tracking = system.hand_tracking.into();
+		}
 	}
 }
 
@@ -50,14 +183,15 @@ pub unsafe extern "system" fn xrGetSystemProperties(
 /// https://www.khronos.org/registry/OpenXR/specs/1.0/html/xrspec.html#xrEnumerateViewConfigurations
 #[no_mangle]
 pub unsafe extern "system" fn xrEnumerateViewConfigurations(
-	_instance: Instance,
-	_system_id: SystemId,
-	_view_configuration_type_capacity_input: u32,
-	_view_configuration_type_count_output: &mut u32,
-	_view_configuration_types: &mut ViewConfigurationType,
+	instance: Instance,
+	system_id: SystemId,
+	view_configuration_type_capacity_input: u32,
+	view_configuration_type_count_output: &mut Option<u32>,
+	view_configuration_types_ptr: *mut ViewConfigurationType,
 ) -> XrResult {
 	wrap_oxr! {
-		todo!();
+		let view_configuration_types = view_configuration_types(&*instance.get_stardust()?, system_id)?;
+		enumerate(view_configuration_type_capacity_input, view_configuration_type_count_output, view_configuration_types_ptr, &view_configuration_types)?;
 	}
 }
 
@@ -65,13 +199,21 @@ pub unsafe extern "system" fn xrEnumerateViewConfigurations(
 /// https://www.khronos.org/registry/OpenXR/specs/1.0/html/xrspec.html#xrGetViewConfigurationProperties
 #[no_mangle]
 pub unsafe extern "system" fn xrGetViewConfigurationProperties(
-	_instance: Instance,
-	_system_id: SystemId,
-	_view_configuration_type: ViewConfigurationType,
-	_configuration_properties: &mut ViewConfigurationProperties,
+	instance: Instance,
+	system_id: SystemId,
+	view_configuration_type: ViewConfigurationType,
+	configuration_properties: &mut ViewConfigurationProperties,
 ) -> XrResult {
+	#[derive(Debug, Deserialize)]
+	struct StardustViewConfigurationProperties {
+		fov_mutable: bool,
+	}
 	wrap_oxr! {
-		todo!();
+		let stardust_instance = instance.get_stardust()?;
+		check_view_configuration_type(&stardust_instance, system_id, view_configuration_type)?;
+		let properties: StardustViewConfigurationProperties = stardust_instance.execute_method(&format!("/openxr/system{}", system_id.into_raw()), "view_configuration_properties", &view_configuration_type.into_raw())?.map_err(|_| XrResult::ERROR_VIEW_CONFIGURATION_TYPE_UNSUPPORTED)?;
+		configuration_properties.view_configuration_type = view_configuration_type;
+		configuration_properties.fov_mutable = properties.fov_mutable.into();
 	}
 }
 
@@ -95,7 +237,8 @@ pub unsafe extern "system" fn xrEnumerateViewConfigurationViews(
 	}
 	wrap_oxr! {
 		let stardust_instance = instance.get_stardust()?;
-		let views: Vec<StardustView> = stardust_instance.execute_method(&format!("/openxr/system{}", system_id.into_raw()), "views", &view_configuration_type.into_raw())?.map_err(|_| XrResult::ERROR_HANDLE_INVALID)?;
+		check_view_configuration_type(&stardust_instance, system_id, view_configuration_type)?;
+		let views: Vec<StardustView> = stardust_instance.execute_method(&format!("/openxr/system{}", system_id.into_raw()), "views", &view_configuration_type.into_raw())?.map_err(|_| XrResult::ERROR_VIEW_CONFIGURATION_TYPE_UNSUPPORTED)?;
 		let views = views.into_iter().map(|v| ViewConfigurationView {
 			ty: StructureType::VIEW_CONFIGURATION_VIEW,
 			next: ptr::null_mut(),
@@ -103,8 +246,9 @@ pub unsafe extern "system" fn xrEnumerateViewConfigurationViews(
 			max_image_rect_width: v.max_image_rect_width,
 			recommended_image_rect_height: v.recommended_image_rect_height,
 			max_image_rect_height: v.max_image_rect_height,
-			recommended_swapchain_sample_count: 1,
-			max_swapchain_sample_count: 1
+			// swapchain images can't be shared multisampled, so whatever the server would like there's only ever 1
+			recommended_swapchain_sample_count: MAX_SWAPCHAIN_SAMPLE_COUNT,
+			max_swapchain_sample_count: MAX_SWAPCHAIN_SAMPLE_COUNT,
 		}).collect::<Vec<_>>();
 
 		enumerate(view_capacity_input, view_count_output, views_ptr, &views)?;
@@ -115,14 +259,15 @@ pub unsafe extern "system" fn xrEnumerateViewConfigurationViews(
 /// https://www.khronos.org/registry/OpenXR/specs/1.0/html/xrspec.html#xrEnumerateEnvironmentBlendModes
 #[no_mangle]
 pub unsafe extern "system" fn xrEnumerateEnvironmentBlendModes(
-	_instance: Instance,
-	_system_id: SystemId,
-	_view_configuration_type: ViewConfigurationType,
-	_environment_blend_mode_capacity_input: u32,
-	_environment_blend_mode_count_output: &mut u32,
-	_environment_blend_modes: &mut EnvironmentBlendMode,
+	instance: Instance,
+	system_id: SystemId,
+	view_configuration_type: ViewConfigurationType,
+	environment_blend_mode_capacity_input: u32,
+	environment_blend_mode_count_output: &mut Option<u32>,
+	environment_blend_modes_ptr: *mut EnvironmentBlendMode,
 ) -> XrResult {
 	wrap_oxr! {
-		todo!();
+		let blend_modes = environment_blend_modes(&*instance.get_stardust()?, system_id, view_configuration_type)?;
+		enumerate(environment_blend_mode_capacity_input, environment_blend_mode_count_output, environment_blend_modes_ptr, &blend_modes)?;
 	}
 }
@@ -1,9 +1,16 @@
-use openxr_sys::LoaderInitInfoBaseHeaderKHR;
+use openxr_sys::{
+	BaseInStructure, BaseOutStructure, Fovf, LoaderInitInfoBaseHeaderKHR, Posef, StructureType,
+	Time,
+};
 
 use crate::XrResult;
+use mint::{Quaternion, Vector3};
+use serde::{Deserialize, Serialize};
 use std::{
-	ffi::{c_char, CStr},
+	ffi::{c_char, c_void, CStr},
 	ptr,
+	sync::{Mutex, MutexGuard, OnceLock, PoisonError},
+	time::Instant,
 };
 
 pub type XrRsResult = Result<(), XrResult>;
@@ -84,20 +91,106 @@ pub unsafe fn get_next_chain<F>(first: &F) -> Vec<LoaderInitInfoBaseHeaderKHR> {
 	chain
 }
 
-pub trait Handle: Sized {
-	type StardustType;
+/// # Safety
+/// `next` must be null or point to a valid chain of OpenXR structs
+pub unsafe fn find_in_next_chain<'a, T>(next: *const c_void, ty: StructureType) -> Option<&'a T> {
+	let mut next = next as *const BaseInStructure;
+	while !next.is_null() {
+		if (*next).ty == ty {
+			return Some(&*(next as *const T));
+		}
+		next = (*next).next;
+	}
+	None
+}
 
-	fn raw(&self) -> u64;
-	fn get_stardust<'a>(&self) -> Result<&'a mut Self::StardustType, XrResult> {
-		let handle = self.raw();
-		if handle == 0 {
-			Err(XrResult::ERROR_HANDLE_INVALID)
-		} else {
-			Ok(unsafe { &mut *(handle as *mut Self::StardustType) })
+/// Like [`find_in_next_chain`], for output structs the runtime fills in.
+///
+/// # Safety
+/// `next` must be null or point to a valid chain of OpenXR structs
+pub unsafe fn find_in_next_chain_mut<'a, T>(
+	next: *mut c_void,
+	ty: StructureType,
+) -> Option<&'a mut T> {
+	let mut next = next as *mut BaseOutStructure;
+	while !next.is_null() {
+		if (*next).ty == ty {
+			return Some(&mut *(next as *mut T));
 		}
+		next = (*next).next;
 	}
-	fn destroy(self) -> Result<(), XrResult> {
-		drop(unsafe { Box::from_raw(self.get_stardust()? as *mut _) });
-		Ok(())
+	None
+}
+
+/// Lock a mutex, carrying on if another thread panicked while holding it.
+///
+/// Our state stays consistent across panics, and an app that caught one shouldn't be locked out of the runtime.
+pub fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+	mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Current runtime time, monotonic and relative to the first time it was queried.
+pub fn now() -> Time {
+	static EPOCH: OnceLock<Instant> = OnceLock::new();
+	// XrTime 0 is reserved as invalid, so offset everything by 1ns
+	Time::from_nanos(EPOCH.get_or_init(Instant::now).elapsed().as_nanos() as i64 + 1)
+}
+/// The server talks in seconds relative to now rather than absolute time, as our clocks don't match.
+pub fn time_from_delay(delay: f64) -> Time {
+	Time::from_nanos(now().as_nanos() + (delay * 1e9) as i64)
+}
+pub fn delay_from_time(time: Time) -> f64 {
+	(time.as_nanos() - now().as_nanos()) as f64 / 1e9
+}
+
+/// A pose as the Stardust server sends and receives it.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct StardustPose {
+	pub position: Vector3<f32>,
+	pub orientation: Quaternion<f32>,
+}
+impl From<StardustPose> for Posef {
+	fn from(pose: StardustPose) -> Self {
+		Posef {
+			orientation: pose.orientation.into(),
+			position: pose.position.into(),
+		}
+	}
+}
+impl From<Posef> for StardustPose {
+	fn from(pose: Posef) -> Self {
+		StardustPose {
+			position: pose.position.into(),
+			orientation: pose.orientation.into(),
+		}
+	}
+}
+
+/// A field of view as the Stardust server sends and receives it, angles in radians.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct StardustFov {
+	pub angle_left: f32,
+	pub angle_right: f32,
+	pub angle_up: f32,
+	pub angle_down: f32,
+}
+impl From<StardustFov> for Fovf {
+	fn from(fov: StardustFov) -> Self {
+		Fovf {
+			angle_left: fov.angle_left,
+			angle_right: fov.angle_right,
+			angle_up: fov.angle_up,
+			angle_down: fov.angle_down,
+		}
+	}
+}
+impl From<Fovf> for StardustFov {
+	fn from(fov: Fovf) -> Self {
+		StardustFov {
+			angle_left: fov.angle_left,
+			angle_right: fov.angle_right,
+			angle_up: fov.angle_up,
+			angle_down: fov.angle_down,
+		}
 	}
 }
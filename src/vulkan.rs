@@ -0,0 +1,573 @@
+use crate::{
+	handle::Handle,
+	session::StardustSession,
+	swapchain::{
+		DmabufImage, OpaqueImage, SwapchainBuffers, DRM_FORMAT_ABGR8888, DRM_FORMAT_ARGB8888,
+	},
+	util::lock,
+	XrResult,
+};
+use ash::{
+	extensions::{ext::ImageDrmFormatModifier, khr::ExternalMemoryFd},
+	vk::{self, Handle as VkHandle},
+};
+use openxr_sys::{
+	platform::{VkDevice, VkGetInstanceProcAddr, VkInstance, VkPhysicalDevice, VkResult},
+	GraphicsBindingVulkanKHR, GraphicsRequirementsVulkanKHR, Instance, SwapchainCreateInfo,
+	SwapchainImageVulkanKHR, SwapchainUsageFlags, SystemId, Version, VulkanDeviceCreateInfoKHR,
+	VulkanGraphicsDeviceGetInfoKHR, VulkanInstanceCreateInfoKHR,
+};
+use std::{
+	ffi::{c_char, CStr},
+	mem::transmute,
+	os::fd::{AsFd, BorrowedFd, FromRawFd, OwnedFd},
+	ptr, slice,
+	sync::atomic::Ordering,
+};
+
+/// External memory and dmabuf export need at least Vulkan 1.1.
+const MIN_VULKAN_VERSION: Version = Version::new(1, 1, 0);
+const MAX_VULKAN_VERSION: Version = Version::new(1, 3, 0);
+
+/// Device extensions we add on top of the app's, so swapchain images can be exported as dmabufs.
+const DEVICE_EXTENSIONS: [&CStr; 4] = [
+	vk::KhrExternalMemoryFdFn::name(),
+	vk::ExtExternalMemoryDmaBufFn::name(),
+	vk::ExtImageDrmFormatModifierFn::name(),
+	vk::KhrImageFormatListFn::name(),
+];
+
+/// Swapchain formats in order of preference, with the DRM fourcc the server imports them as.
+const SWAPCHAIN_FORMATS: [(vk::Format, u32); 4] = [
+	(vk::Format::R8G8B8A8_SRGB, DRM_FORMAT_ABGR8888),
+	(vk::Format::B8G8R8A8_SRGB, DRM_FORMAT_ARGB8888),
+	(vk::Format::R8G8B8A8_UNORM, DRM_FORMAT_ABGR8888),
+	(vk::Format::B8G8R8A8_UNORM, DRM_FORMAT_ARGB8888),
+];
+/// Depth formats in order of preference.
+const DEPTH_FORMATS: [vk::Format; 4] = [
+	vk::Format::D32_SFLOAT,
+	vk::Format::D24_UNORM_S8_UINT,
+	vk::Format::D16_UNORM,
+	vk::Format::D32_SFLOAT_S8_UINT,
+];
+pub fn swapchain_formats() -> Vec<i64> {
+	SWAPCHAIN_FORMATS
+		.iter()
+		.map(|(format, _)| *format)
+		.chain(DEPTH_FORMATS)
+		.map(|format| format.as_raw() as i64)
+		.collect()
+}
+fn is_srgb(format: vk::Format) -> bool {
+	matches!(
+		format,
+		vk::Format::R8G8B8A8_SRGB | vk::Format::B8G8R8A8_SRGB
+	)
+}
+
+/// Vulkan objects the app created through us, needed to create the next ones.
+#[derive(Default)]
+pub struct VulkanContext {
+	get_instance_proc_addr: Option<vk::PFN_vkGetInstanceProcAddr>,
+	instance: Option<vk::Instance>,
+	physical_device: Option<vk::PhysicalDevice>,
+}
+impl VulkanContext {
+	pub fn physical_device(&self) -> Option<vk::PhysicalDevice> {
+		self.physical_device
+	}
+	pub fn ash_instance(&self) -> Option<ash::Instance> {
+		let static_fn = vk::StaticFn {
+			get_instance_proc_addr: self.get_instance_proc_addr?,
+		};
+		Some(unsafe { ash::Instance::load(&static_fn, self.instance?) })
+	}
+}
+
+/// The app's Vulkan objects from `XrGraphicsBindingVulkanKHR`.
+#[derive(Debug, Clone, Copy)]
+pub struct VulkanBinding {
+	pub instance: vk::Instance,
+	pub physical_device: vk::PhysicalDevice,
+	pub device: vk::Device,
+	pub queue_family_index: u32,
+	pub queue_index: u32,
+}
+impl VulkanBinding {
+	pub fn new(
+		context: &VulkanContext,
+		binding: &GraphicsBindingVulkanKHR,
+	) -> Result<Self, XrResult> {
+		let physical_device = vk::PhysicalDevice::from_raw(binding.physical_device as u64);
+		if binding.device.is_null() || context.physical_device != Some(physical_device) {
+			return Err(XrResult::ERROR_GRAPHICS_DEVICE_INVALID);
+		}
+		Ok(VulkanBinding {
+			instance: vk::Instance::from_raw(binding.instance as u64),
+			physical_device,
+			device: vk::Device::from_raw(binding.device as u64),
+			queue_family_index: binding.queue_family_index,
+			queue_index: binding.queue_index,
+		})
+	}
+}
+
+fn image_usage(usage_flags: SwapchainUsageFlags) -> vk::ImageUsageFlags {
+	let mut usage = vk::ImageUsageFlags::empty();
+	if usage_flags.contains(SwapchainUsageFlags::COLOR_ATTACHMENT) {
+		usage |= vk::ImageUsageFlags::COLOR_ATTACHMENT;
+	}
+	if usage_flags.contains(SwapchainUsageFlags::DEPTH_STENCIL_ATTACHMENT) {
+		usage |= vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT;
+	}
+	if usage_flags.contains(SwapchainUsageFlags::UNORDERED_ACCESS) {
+		usage |= vk::ImageUsageFlags::STORAGE;
+	}
+	if usage_flags.contains(SwapchainUsageFlags::TRANSFER_SRC) {
+		usage |= vk::ImageUsageFlags::TRANSFER_SRC;
+	}
+	if usage_flags.contains(SwapchainUsageFlags::TRANSFER_DST) {
+		usage |= vk::ImageUsageFlags::TRANSFER_DST;
+	}
+	if usage_flags.contains(SwapchainUsageFlags::SAMPLED) {
+		usage |= vk::ImageUsageFlags::SAMPLED;
+	}
+	if usage_flags.contains(SwapchainUsageFlags::INPUT_ATTACHMENT) {
+		usage |= vk::ImageUsageFlags::INPUT_ATTACHMENT;
+	}
+	usage
+}
+
+/// Single plane DRM format modifiers the driver can create images of `format` with.
+unsafe fn driver_modifiers(
+	instance: &ash::Instance,
+	physical_device: vk::PhysicalDevice,
+	format: vk::Format,
+) -> Vec<u64> {
+	let mut modifier_list = vk::DrmFormatModifierPropertiesListEXT::default();
+	instance.get_physical_device_format_properties2(
+		physical_device,
+		format,
+		&mut vk::FormatProperties2::builder().push_next(&mut modifier_list),
+	);
+	let mut modifiers = vec![
+		vk::DrmFormatModifierPropertiesEXT::default();
+		modifier_list.drm_format_modifier_count as usize
+	];
+	modifier_list.p_drm_format_modifier_properties = modifiers.as_mut_ptr();
+	instance.get_physical_device_format_properties2(
+		physical_device,
+		format,
+		&mut vk::FormatProperties2::builder().push_next(&mut modifier_list),
+	);
+	modifiers.truncate(modifier_list.drm_format_modifier_count as usize);
+	modifiers
+		.into_iter()
+		.filter(|m| m.drm_format_modifier_plane_count == 1)
+		.map(|m| m.drm_format_modifier)
+		.collect()
+}
+
+/// Swapchain images allocated on the app's device, with memory exported to the server as dmabufs or opaque fds.
+pub struct VulkanSwapchainImages {
+	device: ash::Device,
+	images: Vec<vk::Image>,
+	memory: Vec<vk::DeviceMemory>,
+	fds: Vec<OwnedFd>,
+}
+impl VulkanSwapchainImages {
+	pub fn new(
+		session: &StardustSession,
+		binding: &VulkanBinding,
+		create_info: &SwapchainCreateInfo,
+		image_count: u32,
+	) -> Result<(Self, SwapchainBuffers), XrResult> {
+		let format = vk::Format::from_raw(create_info.format as i32);
+		// depth images get shared as opaque fds, as drivers don't lay them out by DRM modifier
+		let fourcc = match SWAPCHAIN_FORMATS.into_iter().find(|(f, _)| *f == format) {
+			Some((_, fourcc)) => Some(fourcc),
+			None if DEPTH_FORMATS.contains(&format) => None,
+			None => return Err(XrResult::ERROR_SWAPCHAIN_FORMAT_UNSUPPORTED),
+		};
+		if create_info.sample_count != 1 {
+			// multisampled images can't be shared across processes
+			return Err(XrResult::ERROR_FEATURE_UNSUPPORTED);
+		}
+
+		let node_path = session.node_path().to_string();
+		let instance = session.instance()?;
+		let vk_instance = lock(&instance.vulkan)
+			.ash_instance()
+			.ok_or(XrResult::ERROR_RUNTIME_FAILURE)?;
+		let modifiers: Vec<u64> = match fourcc {
+			Some(fourcc) => {
+				let server_modifiers: Vec<u64> = instance
+					.execute_method(&node_path, "dmabuf_modifiers", &fourcc)?
+					.map_err(|_| XrResult::ERROR_RUNTIME_FAILURE)?;
+				let modifiers: Vec<u64> =
+					unsafe { driver_modifiers(&vk_instance, binding.physical_device, format) }
+						.into_iter()
+						.filter(|m| server_modifiers.contains(m))
+						.collect();
+				if modifiers.is_empty() {
+					return Err(XrResult::ERROR_SWAPCHAIN_FORMAT_UNSUPPORTED);
+				}
+				modifiers
+			}
+			None => Vec::new(),
+		};
+		let (tiling, handle_type) = match fourcc {
+			Some(_) => (
+				vk::ImageTiling::DRM_FORMAT_MODIFIER_EXT,
+				vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT,
+			),
+			None => (
+				vk::ImageTiling::OPTIMAL,
+				vk::ExternalMemoryHandleTypeFlags::OPAQUE_FD,
+			),
+		};
+
+		let device = unsafe { ash::Device::load(vk_instance.fp_v1_0(), binding.device) };
+		let external_memory_fd = ExternalMemoryFd::new(&vk_instance, &device);
+		let image_drm_format_modifier = ImageDrmFormatModifier::new(&vk_instance, &device);
+		let memory_properties =
+			unsafe { vk_instance.get_physical_device_memory_properties(binding.physical_device) };
+
+		// the sRGB and UNORM variants of a format share a fourcc, so those are what it can be viewed as
+		let view_formats: Vec<vk::Format> = SWAPCHAIN_FORMATS
+			.into_iter()
+			.filter(|(_, f)| fourcc == Some(*f))
+			.map(|(format, _)| format)
+			.collect();
+		let mut flags = vk::ImageCreateFlags::empty();
+		if create_info.face_count == 6 {
+			flags |= vk::ImageCreateFlags::CUBE_COMPATIBLE;
+		}
+		if create_info
+			.usage_flags
+			.contains(SwapchainUsageFlags::MUTABLE_FORMAT)
+		{
+			flags |= vk::ImageCreateFlags::MUTABLE_FORMAT;
+		}
+		let usage = image_usage(create_info.usage_flags);
+		let array_layers = create_info.array_size * create_info.face_count;
+
+		// anything that fails partway through is cleaned up by dropping this
+		let mut swapchain_images = VulkanSwapchainImages {
+			device,
+			images: Vec::new(),
+			memory: Vec::new(),
+			fds: Vec::new(),
+		};
+		let mut dmabuf_images = Vec::new();
+		let mut opaque_images = Vec::new();
+		for _ in 0..image_count {
+			let mut modifier_list = vk::ImageDrmFormatModifierListCreateInfoEXT::builder()
+				.drm_format_modifiers(&modifiers);
+			let mut format_list =
+				vk::ImageFormatListCreateInfo::builder().view_formats(&view_formats);
+			let mut external_memory =
+				vk::ExternalMemoryImageCreateInfo::builder().handle_types(handle_type);
+			let mut image_info = vk::ImageCreateInfo::builder()
+				.flags(flags)
+				.image_type(vk::ImageType::TYPE_2D)
+				.format(format)
+				.extent(vk::Extent3D {
+					width: create_info.width,
+					height: create_info.height,
+					depth: 1,
+				})
+				.mip_levels(create_info.mip_count)
+				.array_layers(array_layers)
+				.samples(vk::SampleCountFlags::TYPE_1)
+				.tiling(tiling)
+				.usage(usage)
+				.sharing_mode(vk::SharingMode::EXCLUSIVE)
+				.initial_layout(vk::ImageLayout::UNDEFINED)
+				.push_next(&mut external_memory);
+			if fourcc.is_some() {
+				image_info = image_info
+					.push_next(&mut modifier_list)
+					.push_next(&mut format_list);
+			}
+			let image = unsafe { swapchain_images.device.create_image(&image_info, None) }
+				.map_err(|_| XrResult::ERROR_RUNTIME_FAILURE)?;
+			swapchain_images.images.push(image);
+
+			let requirements =
+				unsafe { swapchain_images.device.get_image_memory_requirements(image) };
+			let memory_type_index = (0..memory_properties.memory_type_count)
+				.find(|i| {
+					requirements.memory_type_bits & (1 << i) != 0
+						&& memory_properties.memory_types[*i as usize]
+							.property_flags
+							.contains(vk::MemoryPropertyFlags::DEVICE_LOCAL)
+				})
+				.ok_or(XrResult::ERROR_RUNTIME_FAILURE)?;
+			let mut dedicated = vk::MemoryDedicatedAllocateInfo::builder().image(image);
+			let mut export = vk::ExportMemoryAllocateInfo::builder().handle_types(handle_type);
+			let allocate_info = vk::MemoryAllocateInfo::builder()
+				.allocation_size(requirements.size)
+				.memory_type_index(memory_type_index)
+				.push_next(&mut dedicated)
+				.push_next(&mut export);
+			let memory = unsafe {
+				swapchain_images
+					.device
+					.allocate_memory(&allocate_info, None)
+			}
+			.map_err(|_| XrResult::ERROR_RUNTIME_FAILURE)?;
+			swapchain_images.memory.push(memory);
+			unsafe { swapchain_images.device.bind_image_memory(image, memory, 0) }
+				.map_err(|_| XrResult::ERROR_RUNTIME_FAILURE)?;
+
+			let fd = unsafe {
+				external_memory_fd.get_memory_fd(
+					&vk::MemoryGetFdInfoKHR::builder()
+						.memory(memory)
+						.handle_type(handle_type),
+				)
+			}
+			.map_err(|_| XrResult::ERROR_RUNTIME_FAILURE)?;
+			let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+			if fourcc.is_none() {
+				opaque_images.push(OpaqueImage {
+					size: requirements.size,
+				});
+				swapchain_images.fds.push(fd);
+				continue;
+			}
+			let mut modifier_properties = vk::ImageDrmFormatModifierPropertiesEXT::default();
+			unsafe {
+				image_drm_format_modifier
+					.get_image_drm_format_modifier_properties(image, &mut modifier_properties)
+			}
+			.map_err(|_| XrResult::ERROR_RUNTIME_FAILURE)?;
+			let layout = unsafe {
+				swapchain_images.device.get_image_subresource_layout(
+					image,
+					vk::ImageSubresource {
+						aspect_mask: vk::ImageAspectFlags::MEMORY_PLANE_0_EXT,
+						mip_level: 0,
+						array_layer: 0,
+					},
+				)
+			};
+			dmabuf_images.push(DmabufImage {
+				modifier: modifier_properties.drm_format_modifier,
+				offset: layout.offset,
+				stride: layout.row_pitch,
+				array_pitch: layout.array_pitch,
+			});
+			swapchain_images.fds.push(fd);
+		}
+
+		let buffers = match fourcc {
+			Some(fourcc) => SwapchainBuffers::Dmabuf {
+				fourcc,
+				srgb: is_srgb(format),
+				flip_y: false,
+				images: dmabuf_images,
+			},
+			None => SwapchainBuffers::VulkanOpaque {
+				format: format.as_raw(),
+				usage: usage.as_raw(),
+				images: opaque_images,
+			},
+		};
+		Ok((swapchain_images, buffers))
+	}
+
+	pub fn xr_images(&self) -> Vec<SwapchainImageVulkanKHR> {
+		self.images
+			.iter()
+			.map(|image| SwapchainImageVulkanKHR {
+				ty: SwapchainImageVulkanKHR::TYPE,
+				next: ptr::null_mut(),
+				image: image.as_raw(),
+			})
+			.collect()
+	}
+	/// The exported memory of each image, in order.
+	pub fn fds(&self) -> Vec<BorrowedFd<'_>> {
+		self.fds.iter().map(AsFd::as_fd).collect()
+	}
+}
+impl Drop for VulkanSwapchainImages {
+	fn drop(&mut self) {
+		// the app has to be done with the images by the time it destroys the swapchain
+		unsafe {
+			for image in self.images.drain(..) {
+				self.device.destroy_image(image, None);
+			}
+			for memory in self.memory.drain(..) {
+				self.device.free_memory(memory, None);
+			}
+		}
+	}
+}
+
+fn static_fn(
+	get_instance_proc_addr: Option<VkGetInstanceProcAddr>,
+) -> Result<vk::StaticFn, XrResult> {
+	let get_instance_proc_addr =
+		get_instance_proc_addr.ok_or(XrResult::ERROR_VALIDATION_FAILURE)?;
+	Ok(vk::StaticFn {
+		get_instance_proc_addr: unsafe {
+			transmute::<VkGetInstanceProcAddr, vk::PFN_vkGetInstanceProcAddr>(
+				get_instance_proc_addr,
+			)
+		},
+	})
+}
+
+/// # Safety
+/// https://www.khronos.org/registry/OpenXR/specs/1.0/html/xrspec.html#xrCreateVulkanInstanceKHR
+#[no_mangle]
+pub unsafe extern "system" fn xrCreateVulkanInstanceKHR(
+	instance: Instance,
+	create_info: &VulkanInstanceCreateInfoKHR,
+	vulkan_instance: &mut VkInstance,
+	vulkan_result: &mut VkResult,
+) -> XrResult {
+	wrap_oxr! {
+		if create_info.vulkan_create_info.is_null() {
+			Err(XrResult::ERROR_VALIDATION_FAILURE)?;
+		}
+		let stardust_instance = instance.get_stardust()?;
+		let static_fn = static_fn(create_info.pfn_get_instance_proc_addr)?;
+		let get_instance_proc_addr = static_fn.get_instance_proc_addr;
+		let entry = ash::Entry::from_static_fn(static_fn);
+
+		let mut vk_instance = vk::Instance::null();
+		let result = (entry.fp_v1_0().create_instance)(
+			create_info.vulkan_create_info as *const vk::InstanceCreateInfo,
+			create_info.vulkan_allocator as *const vk::AllocationCallbacks,
+			&mut vk_instance,
+		);
+		*vulkan_result = result.as_raw();
+		if result == vk::Result::SUCCESS {
+			*vulkan_instance = vk_instance.as_raw() as VkInstance;
+			let mut vulkan = lock(&stardust_instance.vulkan);
+			vulkan.get_instance_proc_addr = Some(get_instance_proc_addr);
+			vulkan.instance = Some(vk_instance);
+		}
+	}
+}
+
+/// # Safety
+/// https://www.khronos.org/registry/OpenXR/specs/1.0/html/xrspec.html#xrGetVulkanGraphicsDevice2KHR
+#[no_mangle]
+pub unsafe extern "system" fn xrGetVulkanGraphicsDevice2KHR(
+	instance: Instance,
+	get_info: &VulkanGraphicsDeviceGetInfoKHR,
+	vulkan_physical_device: &mut VkPhysicalDevice,
+) -> XrResult {
+	wrap_oxr! {
+		let stardust_instance = instance.get_stardust()?;
+		let vk_instance = {
+			let mut vulkan = lock(&stardust_instance.vulkan);
+			vulkan.instance = Some(vk::Instance::from_raw(get_info.vulkan_instance as u64));
+			vulkan.ash_instance().ok_or(XrResult::ERROR_VALIDATION_FAILURE)?
+		};
+		let physical_devices = vk_instance.enumerate_physical_devices().map_err(|_| XrResult::ERROR_RUNTIME_FAILURE)?;
+
+		// the compositor's GPU, so it can import our images without a copy
+		let device_uuid: Option<Vec<u8>> = stardust_instance
+			.execute_method(&format!("/openxr/system{}", get_info.system_id.into_raw()), "vulkan_device_uuid", &())?
+			.map_err(|_| XrResult::ERROR_SYSTEM_INVALID)?;
+		let physical_device = physical_devices.iter().copied().find(|physical_device| {
+			let mut id_properties = vk::PhysicalDeviceIDProperties::default();
+			let mut properties = vk::PhysicalDeviceProperties2::builder().push_next(&mut id_properties);
+			vk_instance.get_physical_device_properties2(*physical_device, &mut properties);
+			device_uuid.as_deref() == Some(&id_properties.device_uuid[..])
+		}).ok_or_else(|| {
+			// any other GPU couldn't import our images, depth included, so don't pretend it can
+			eprintln!("None of the Vulkan physical devices is the Stardust server's GPU ({device_uuid:02x?})");
+			XrResult::ERROR_RUNTIME_FAILURE
+		})?;
+
+		lock(&stardust_instance.vulkan).physical_device = Some(physical_device);
+		*vulkan_physical_device = physical_device.as_raw() as VkPhysicalDevice;
+	}
+}
+
+/// # Safety
+/// https://www.khronos.org/registry/OpenXR/specs/1.0/html/xrspec.html#xrCreateVulkanDeviceKHR
+#[no_mangle]
+pub unsafe extern "system" fn xrCreateVulkanDeviceKHR(
+	instance: Instance,
+	create_info: &VulkanDeviceCreateInfoKHR,
+	vulkan_device: &mut VkDevice,
+	vulkan_result: &mut VkResult,
+) -> XrResult {
+	wrap_oxr! {
+		if create_info.vulkan_create_info.is_null() || create_info.pfn_get_instance_proc_addr.is_none() {
+			Err(XrResult::ERROR_VALIDATION_FAILURE)?;
+		}
+		let stardust_instance = instance.get_stardust()?;
+		let physical_device = vk::PhysicalDevice::from_raw(create_info.vulkan_physical_device as u64);
+		let vulkan_instance = {
+			let vulkan = lock(&stardust_instance.vulkan);
+			if vulkan.physical_device != Some(physical_device) {
+				Err(XrResult::ERROR_GRAPHICS_DEVICE_INVALID)?;
+			}
+			vulkan.instance.ok_or(XrResult::ERROR_VALIDATION_FAILURE)?
+		};
+		let static_fn = static_fn(create_info.pfn_get_instance_proc_addr)?;
+		let vk_instance = ash::Instance::load(&static_fn, vulkan_instance);
+
+		// the app's create info with the extensions swapchain export needs tacked on
+		let mut device_create_info = *(create_info.vulkan_create_info as *const vk::DeviceCreateInfo);
+		let mut extension_names: Vec<*const c_char> = if device_create_info.enabled_extension_count == 0 {
+			Vec::new()
+		} else {
+			slice::from_raw_parts(device_create_info.pp_enabled_extension_names, device_create_info.enabled_extension_count as usize).to_vec()
+		};
+		let supported_extensions = vk_instance.enumerate_device_extension_properties(physical_device).map_err(|_| XrResult::ERROR_RUNTIME_FAILURE)?;
+		for extension in DEVICE_EXTENSIONS {
+			let supported = supported_extensions.iter().any(|e| CStr::from_ptr(e.extension_name.as_ptr()) == extension);
+			if !supported {
+				// better here than every swapchain failing to export later on for no clear reason
+				eprintln!("Vulkan device doesn't support {extension:?}, which swapchains need to be shared with the Stardust server");
+				*vulkan_result = vk::Result::ERROR_EXTENSION_NOT_PRESENT.as_raw();
+				Err(XrResult::ERROR_RUNTIME_FAILURE)?;
+			}
+			let enabled = extension_names.iter().any(|name| CStr::from_ptr(*name) == extension);
+			if !enabled {
+				extension_names.push(extension.as_ptr());
+			}
+		}
+		device_create_info.enabled_extension_count = extension_names.len() as u32;
+		device_create_info.pp_enabled_extension_names = extension_names.as_ptr();
+
+		let mut device = vk::Device::null();
+		let result = (vk_instance.fp_v1_0().create_device)(
+			physical_device,
+			&device_create_info,
+			create_info.vulkan_allocator as *const vk::AllocationCallbacks,
+			&mut device,
+		);
+		*vulkan_result = result.as_raw();
+		if result == vk::Result::SUCCESS {
+			*vulkan_device = device.as_raw() as VkDevice;
+		}
+	}
+}
+
+/// # Safety
+/// https://www.khronos.org/registry/OpenXR/specs/1.0/html/xrspec.html#xrGetVulkanGraphicsRequirements2KHR
+#[no_mangle]
+pub unsafe extern "system" fn xrGetVulkanGraphicsRequirements2KHR(
+	instance: Instance,
+	_system_id: SystemId,
+	graphics_requirements: &mut GraphicsRequirementsVulkanKHR,
+) -> XrResult {
+	wrap_oxr! {
+		instance.get_stardust()?.graphics_requirements_queried.store(true, Ordering::Release);
+		graphics_requirements.min_api_version_supported = MIN_VULKAN_VERSION;
+		graphics_requirements.max_api_version_supported = MAX_VULKAN_VERSION;
+	}
+}